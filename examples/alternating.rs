@@ -5,7 +5,7 @@ extern crate env_logger;
 
 #[macro_use] extern crate log;
 
-use std::io::Write;
+use std::io::{self, Write};
 use std::env;
 use std::time::Duration;
 
@@ -42,16 +42,13 @@ fn main() {
         .sleep_on_error(Duration::from_millis(100))
         .map(move |mut socket| {
             Delay::new(clock::now() + Duration::from_millis(500))
-            .map(move |_| socket.write(b"hello\n"))
-            .map(|result| {
-                match result {
-                    Ok(_) => (),
-                    Err(e) => error!("Conn error: {}", e),
-                }
-            })
-            .map_err(|_| ())
+            .map_err(io::Error::other)
+            .and_then(move |_| socket.write(b"hello\n"))
+            .map(|_| ())
+        })
+        .listen_with_errors(1000, |_peer, e| {  // max connections
+            error!("Conn error: {}", e)
         })
-        .listen(1000)  // max connections
     );
 
     tokio::run(
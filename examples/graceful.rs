@@ -0,0 +1,70 @@
+extern crate tokio;
+extern crate futures;
+extern crate tk_listen;
+extern crate env_logger;
+
+#[macro_use] extern crate log;
+
+use std::env;
+use std::io;
+use std::time::Duration;
+
+use tokio::clock;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::timer::{Delay, Interval};
+use futures::{Future, Stream};
+
+use tk_listen::{ListenExt, Shutdown};
+
+
+fn main() {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "info");
+    }
+    env_logger::init();
+
+    let addr = "0.0.0.0:8080".parse().unwrap();
+    let listener = TcpListener::bind(&addr).unwrap();
+    let shutdown = Shutdown::new();
+
+    println!("This program will greet clients each second \
+              and will shut down in 10 seconds");
+
+    let mut runtime = Runtime::new().unwrap();
+    let trigger = shutdown.clone();
+    runtime.spawn(
+        Delay::new(clock::now() + Duration::new(10, 0))
+        .map(move |()| trigger.shutdown())
+        .map_err(|e| error!("Timer error: {}", e))
+    );
+
+    runtime.block_on(
+        listener.incoming()
+        .sleep_on_error(Duration::from_millis(100))
+        .with_shutdown(&shutdown)
+        .map(move |(socket, mut token)| {
+            Interval::new(clock::now(), Duration::new(1, 0))
+            .map_err(io::Error::other)
+            // finish current greeting and stop on shutdown
+            .take_while(move |_| {
+                if token.is_shutting_down() {
+                    token.acknowledge();
+                    Ok(false)
+                } else {
+                    Ok(true)
+                }
+            })
+            .fold(socket, |socket, _| {
+                tokio::io::write_all(socket, b"hello\n")
+                .map(|(socket, _)| socket)
+            })
+            .and_then(|socket| tokio::io::write_all(socket, b"bye\n"))
+            .map(|_| ())
+        })
+        .listen_with_errors(1000, |_peer, e| {  // max connections
+            error!("Conn error: {}", e)
+        })
+    ).unwrap();
+    info!("All connections are closed");
+}
@@ -21,7 +21,7 @@ use futures::future::{FutureResult, ok};
 use tk_http::{Status};
 use tk_http::server::buffered::{Request, BufferedDispatcher};
 use tk_http::server::{self, Encoder, EncoderDone, Proto, Error};
-use tk_listen::ListenExt;
+use tk_listen::{ListenExt, Peer};
 
 const BODY: &'static str = "Hello World!";
 
@@ -56,13 +56,13 @@ fn main() {
         listener.incoming()
         .sleep_on_error(Duration::from_millis(100), &h2)
         .map(move |(socket, addr)| {
-            Proto::new(socket, &scfg,
+            Peer::new(addr, Proto::new(socket, &scfg,
                 BufferedDispatcher::new(addr, &h1, || service),
-                &h1)
-            // Log error, effectively making error type of a future nil `()`
-            .map_err(|e| { println!("Connection error: {}", e); })
+                &h1))
+        })
+        .listen_with_errors(1000, |peer, e| {  // max connections
+            println!("Connection error {:?}: {}", peer, e);
         })
-        .listen(1000)  // max connections
     ).unwrap();
 }
 */
@@ -0,0 +1,36 @@
+use std::env;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures03::{stream, AsyncWriteExt, StreamExt};
+use smol::Timer;
+use tk_listen::std_future::{AsyncIo, BindMany, ListenExt};
+
+
+const MAX_SIMULTANEOUS_CONNECTIONS: usize = 1000;
+const TIME_TO_WAIT_ON_ERROR: Duration = Duration::from_millis(100);
+
+
+fn main() {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "info");
+    }
+    env_logger::init();
+
+    let addr: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+    // a list of addresses is never updated in this example
+    let addresses = stream::once(async move { vec![addr] })
+        .chain(stream::pending());
+
+    smol::block_on(
+        BindMany::with_runtime(addresses, AsyncIo)
+        .sleep_on_error_with(AsyncIo, TIME_TO_WAIT_ON_ERROR)
+        .map(|mut socket| async move {
+            Timer::after(Duration::from_millis(500)).await;
+            if let Err(e) = socket.write_all(b"hello\n").await {
+                log::error!("Conn error: {}", e);
+            }
+        })
+        .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+    );
+}
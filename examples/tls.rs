@@ -0,0 +1,63 @@
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures03::{stream, StreamExt};
+use tk_listen::std_future::{BindMany, ListenExt};
+use tokio1::io::AsyncWriteExt;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+
+
+const MAX_SIMULTANEOUS_CONNECTIONS: usize = 1000;
+const MAX_SIMULTANEOUS_HANDSHAKES: usize = 100;
+const TIME_TO_WAIT_ON_ERROR: Duration = Duration::from_millis(100);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+#[tokio1::main(crate = "tokio1")]
+async fn main() {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "info");
+    }
+    env_logger::init();
+
+    let mut args = env::args().skip(1);
+    let (cert, key) = match (args.next(), args.next()) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => {
+            eprintln!("Usage: tls <cert.pem> <key.pem>");
+            return;
+        }
+    };
+    let certs = CertificateDer::pem_file_iter(&cert)
+        .expect("can open certificate")
+        .collect::<Result<Vec<_>, _>>()
+        .expect("valid certificate");
+    let key = PrivateKeyDer::from_pem_file(&key).expect("valid key");
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .expect("valid certificate and key");
+
+    let addr: SocketAddr = "0.0.0.0:8443".parse().unwrap();
+    // a list of addresses is never updated in this example
+    let addresses = stream::once(async move { vec![addr] })
+        .chain(stream::pending());
+
+    let mut tls = BindMany::new(addresses)
+        .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+        .tls_handshake(Arc::new(config), HANDSHAKE_TIMEOUT);
+    tls.max_handshakes(MAX_SIMULTANEOUS_HANDSHAKES);
+    tls
+        .map(|mut socket| async move {
+            if let Err(e) = socket.write_all(b"hello\n").await {
+                log::error!("Conn error: {}", e);
+            }
+            socket.shutdown().await.ok();
+        })
+        .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+        .await;
+}
@@ -17,7 +17,7 @@ use std::time::Duration;
 use tokio_core::reactor::{Core, Timeout};
 use futures::{Future, Stream};
 
-use tk_listen::{ListenExt, BindMany};
+use tk_listen::{ListenExt, BindMany, Peer};
 
 
 fn main() {
@@ -40,13 +40,15 @@ fn main() {
                 .map(|a| a.addresses_at(0)),
             &h1)
         .sleep_on_error(Duration::from_millis(100), &h2)
-        .map(move |(mut socket, _addr)| {
-            Timeout::new(Duration::from_millis(500), &h1).unwrap()
-            .and_then(move |_| socket.write(b"hello\n"))
-            .map(|_nbytes| ())
-            .map_err(|e| error!("Conn error: {}", e))
+        .map(move |(mut socket, addr)| {
+            Peer::new(addr,
+                Timeout::new(Duration::from_millis(500), &h1).unwrap()
+                .and_then(move |_| socket.write(b"hello\n"))
+                .map(|_nbytes| ()))
+        })
+        .listen_with_errors(1000, |peer, e| {  // max connections
+            error!("Conn error {:?}: {}", peer, e);
         })
-        .listen(1000)  // max connections
     ).unwrap();
 }
 */
@@ -1,3 +1,4 @@
+use std::cmp::min;
 use std::collections::HashMap;
 use std::io;
 use std::mem;
@@ -5,10 +6,14 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use futures::{Future, Stream, Async};
-use tokio::net::{TcpListener, Incoming, TcpStream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::Incoming;
 use tokio::clock;
 use tokio::timer::Delay;
 
+use crate::rate_limit::{self, Bucket};
+use crate::saturation::Saturation;
+
 
 
 /// This stream replaces ``tokio_core::net::Incoming`` and listens many sockets
@@ -40,15 +45,16 @@ use tokio::timer::Delay;
 ///  ```rust,ignore
 ///    lp.run(
 ///        BindMany::new(address_stream)
-///        .sleep_on_error(TIME_TO_WAIT_ON_ERROR, &h2)
-///        .map(move |(mut socket, _addr)| {
+///        .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+///        .map(move |mut socket| {
 ///             // Your future is here:
 ///             Proto::new(socket)
-///             // Errors should not pass silently
-///             // common idea is to log them
-///             .map_err(|e| error!("Protocol error: {}", e))
 ///        })
-///        .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+///        // Errors should not pass silently
+///        // common idea is to log them
+///        .listen_with_errors(MAX_SIMULTANEOUS_CONNECTIONS, |_peer, e| {
+///             error!("Protocol error: {}", e)
+///        })
 ///    ).unwrap(); // stream doesn't end in this case
 ///  ```
 ///
@@ -62,15 +68,16 @@ use tokio::timer::Delay;
 ///      lp.run(
 ///          BindMany::new(ns.resolve_auto("localhost", 8080)
 ///             .map(|addr| addr.addresses_at(0)))
-///          .sleep_on_error(TIME_TO_WAIT_ON_ERROR, &h2)
-///          .map(move |(mut socket, _addr)| {
+///          .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+///          .map(move |mut socket| {
 ///               // Your future is here:
 ///               Proto::new(socket)
-///               // Errors should not pass silently
-///               // common idea is to log them
-///               .map_err(|e| eprintln!("Protocol error: {}", e))
 ///          })
-///          .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+///          // Errors should not pass silently
+///          // common idea is to log them
+///          .listen_with_errors(MAX_SIMULTANEOUS_CONNECTIONS, |_peer, e| {
+///               eprintln!("Protocol error: {}", e)
+///          })
 ///      ).unwrap(); // stream doesn't end in this case
 ///  ```
 ///
@@ -79,7 +86,27 @@ pub struct BindMany<S> {
     addresses: S,
     retry_interval: Duration,
     retry_timer: Option<(Delay, Vec<SocketAddr>)>,
-    inputs: HashMap<SocketAddr, Incoming>,
+    rate: Option<(u32, u32)>,
+    rate_timer: Option<Delay>,
+    saturation: Option<Saturation>,
+    paused: Option<Vec<SocketAddr>>,
+    inputs: HashMap<SocketAddr, Input>,
+}
+
+struct Input {
+    incoming: Incoming,
+    bucket: Option<Bucket>,
+}
+
+impl Input {
+    fn new(listener: TcpListener, rate: Option<(u32, u32)>) -> Input {
+        Input {
+            incoming: listener.incoming(),
+            bucket: rate.map(|(per_second, burst)| {
+                Bucket::new(per_second, burst)
+            }),
+        }
+    }
 }
 
 impl<S> BindMany<S> {
@@ -90,6 +117,10 @@ impl<S> BindMany<S> {
             addresses: s,
             retry_interval: Duration::new(1, 0),
             retry_timer: None,
+            rate: None,
+            rate_timer: None,
+            saturation: None,
+            paused: None,
             inputs: HashMap::new(),
         }
     }
@@ -111,6 +142,40 @@ impl<S> BindMany<S> {
         self.retry_interval = interval;
         self
     }
+
+    /// Limits the rate of accepted connections on each listening socket
+    ///
+    /// Every socket gets its own token bucket with `burst` tokens which is
+    /// refilled at `per_second` rate. When bucket is empty, socket isn't
+    /// polled, so clients wait in the kernel backlog. See
+    /// `ListenExt::rate_limit` for a limit common for all sockets.
+    pub fn socket_rate_limit(&mut self, per_second: u32, burst: u32)
+        -> &mut Self
+    {
+        self.rate = Some((per_second, burst));
+        for inp in self.inputs.values_mut() {
+            inp.bucket = Some(Bucket::new(per_second, burst));
+        }
+        self
+    }
+
+    /// Closes listening sockets while listener is saturated
+    ///
+    /// Sockets are closed when `Listen` (which must be attached to the
+    /// same `saturation` with `Listen::saturation`) has been at the limit
+    /// for the configured time, and are bound again when listener has
+    /// free capacity. See `Saturation` for more info.
+    ///
+    /// Note: all sockets of this `BindMany` are closed, so sockets which
+    /// must stay open (like admin or health check ones) should be bound by
+    /// a separate `BindMany` and `Listen` which aren't attached to the
+    /// `saturation` (use `SharedLimit` if they need a common limit).
+    pub fn close_when_saturated(&mut self, saturation: &Saturation)
+        -> &mut Self
+    {
+        self.saturation = Some(saturation.clone());
+        self
+    }
 }
 
 impl<S> Stream for BindMany<S>
@@ -126,17 +191,22 @@ impl<S> Stream for BindMany<S>
                     info!("Listening stream reached end-of-stream condition");
                     return Ok(Async::Ready(None));
                 }
+                Ok(Async::Ready(Some(new))) if self.paused.is_some() => {
+                    // will be bound when listener isn't saturated
+                    self.paused = Some(new.into_iter().collect());
+                }
                 Ok(Async::Ready(Some(new))) => {
-                    let mut old = mem::replace(&mut self.inputs,
-                                               HashMap::new());
+                    let mut old = mem::take(&mut self.inputs);
                     let mut backlog = Vec::new();
+                    let rate = self.rate;
                     for addr in new {
                         if let Some(listener) = old.remove(&addr) {
                             self.inputs.insert(addr, listener);
                         } else {
                             match TcpListener::bind(&addr) {
                                 Ok(l) =>  {
-                                    self.inputs.insert(addr, l.incoming());
+                                    self.inputs.insert(addr,
+                                                       Input::new(l, rate));
                                 }
                                 Err(e) => {
                                     backlog.push(addr);
@@ -147,7 +217,7 @@ impl<S> Stream for BindMany<S>
                             }
                         }
                     }
-                    if backlog.len() > 0 {
+                    if !backlog.is_empty() {
                         self.retry_timer = Some((
                             Delay::new(clock::now() + self.retry_interval),
                             backlog));
@@ -162,14 +232,52 @@ impl<S> Stream for BindMany<S>
                 }
             }
         }
+        if let Some(ref saturation) = self.saturation {
+            saturation.register();
+            if saturation.is_saturated() {
+                if self.paused.is_none() {
+                    info!("Listener is saturated, closing listening sockets");
+                    let mut addrs = self.inputs.drain()
+                        .map(|(addr, _)| addr)
+                        .collect::<Vec<_>>();
+                    if let Some((_, backlog)) = self.retry_timer.take() {
+                        addrs.extend(backlog);
+                    }
+                    self.paused = Some(addrs);
+                }
+                return Ok(Async::NotReady);
+            }
+        }
+        if let Some(addrs) = self.paused.take() {
+            info!("Listener has free capacity, binding sockets again");
+            let mut backlog = Vec::new();
+            for addr in addrs {
+                match TcpListener::bind(&addr) {
+                    Ok(l) => {
+                        self.inputs.insert(addr, Input::new(l, self.rate));
+                    }
+                    Err(e) => {
+                        backlog.push(addr);
+                        error!("Error binding {:?}: {}, will retry in {:?}",
+                            addr, e, self.retry_interval);
+                    }
+                }
+            }
+            if !backlog.is_empty() {
+                self.retry_timer = Some((
+                    Delay::new(clock::now() + self.retry_interval),
+                    backlog));
+            }
+        }
         loop {
             if let Some((ref mut timer, ref mut backlog)) = self.retry_timer {
                 match timer.poll().expect("deadline never fails") {
                     Async::Ready(()) => {
-                        for addr in mem::replace(backlog, Vec::new()) {
+                        for addr in mem::take(backlog) {
                             match TcpListener::bind(&addr) {
                                 Ok(l) =>  {
-                                    self.inputs.insert(addr, l.incoming());
+                                    let inp = Input::new(l, self.rate);
+                                    self.inputs.insert(addr, inp);
                                 }
                                 Err(e) => {
                                     backlog.push(addr);
@@ -180,7 +288,7 @@ impl<S> Stream for BindMany<S>
                                 }
                             }
                         }
-                        if backlog.len() > 0 {
+                        if !backlog.is_empty() {
                             *timer = Delay::new(
                                 clock::now() + self.retry_interval
                             );
@@ -194,17 +302,113 @@ impl<S> Stream for BindMany<S>
             self.retry_timer = None;
             break;
         }
-        for inp in self.inputs.values_mut() {
-            loop {
-                match inp.poll() {
+        loop {
+            let now = clock::now();
+            let mut wait: Option<Duration> = None;
+            for inp in self.inputs.values_mut() {
+                if let Some(ref mut bucket) = inp.bucket {
+                    if let Some(delay) = bucket.check(now) {
+                        wait = Some(wait.map_or(delay, |w| min(w, delay)));
+                        continue;
+                    }
+                }
+                match inp.incoming.poll() {
                     Ok(Async::Ready(pair)) => {
+                        if let Some(ref mut bucket) = inp.bucket {
+                            bucket.take();
+                        }
                         return Ok(Async::Ready(pair));
                     }
-                    Ok(Async::NotReady) => break,
+                    Ok(Async::NotReady) => continue,
                     Err(e) => return Err(e),
                 }
             }
+            match wait {
+                Some(delay) => {
+                    if rate_limit::wait(&mut self.rate_timer, now, delay) {
+                        continue;
+                    }
+                }
+                None => self.rate_timer = None,
+            }
+            return Ok(Async::NotReady);
         }
-        return Ok(Async::NotReady);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::net::{self, SocketAddr};
+    use std::time::Duration;
+
+    use futures::Stream;
+    use futures::future::lazy;
+    use futures::sync::mpsc;
+    use tokio::runtime::current_thread::Runtime;
+
+    use crate::saturation::Saturation;
+    use super::BindMany;
+
+    fn free_addr() -> SocketAddr {
+        net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    fn connect(addr: SocketAddr) -> io::Result<net::TcpStream> {
+        net::TcpStream::connect(addr)
+    }
+
+    #[test]
+    fn saturation_pause_and_rebind() {
+        let mut runtime = Runtime::new().unwrap();
+        let (first, second) = (free_addr(), free_addr());
+        let saturation = Saturation::new(Duration::new(0, 0));
+        let (tx, rx) = mpsc::unbounded::<Vec<SocketAddr>>();
+        let mut bind = BindMany::new(rx);
+        bind.close_when_saturated(&saturation);
+        tx.unbounded_send(vec![first]).unwrap();
+
+        connect(first).unwrap_err();
+        runtime.block_on(lazy(|| {
+            assert!(bind.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+        let client = connect(first).unwrap();
+        let (sock, rest) = runtime.block_on(bind.into_future())
+            .map_err(|(e, _)| e).unwrap();
+        bind = rest;
+        assert_eq!(sock.unwrap().peer_addr().unwrap(),
+                   client.local_addr().unwrap());
+
+        // sockets are closed when listener is saturated
+        saturation.set_at_limit(true);
+        assert!(saturation.is_saturated());
+        runtime.block_on(lazy(|| {
+            assert!(bind.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert_eq!(connect(first).unwrap_err().kind(),
+                   io::ErrorKind::ConnectionRefused);
+
+        // address changes are applied when sockets are bound again
+        tx.unbounded_send(vec![second]).unwrap();
+        runtime.block_on(lazy(|| {
+            assert!(bind.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+        connect(second).unwrap_err();
+
+        saturation.set_at_limit(false);
+        assert!(!saturation.is_saturated());
+        runtime.block_on(lazy(|| {
+            assert!(bind.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+        connect(first).unwrap_err();
+        let client = connect(second).unwrap();
+        let (sock, _) = runtime.block_on(bind.into_future())
+            .map_err(|(e, _)| e).unwrap();
+        assert_eq!(sock.unwrap().peer_addr().unwrap(),
+                   client.local_addr().unwrap());
     }
 }
@@ -0,0 +1,288 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::Duration;
+
+use futures::{Future, IntoFuture, Async};
+use futures::task;
+use tokio::clock;
+use tokio::timer::Delay;
+
+use crate::util::panic_message;
+
+
+/// A connection handler that can be run by `Listen`
+///
+/// This is implemented for every `IntoFuture<Item=()>` and for the
+/// `Peer` wrapper which additionally tells the listener address of the
+/// remote peer (used for logging).
+pub trait Connection {
+    /// Error returned by connection future
+    type Error;
+    /// Future that handles the connection
+    type Future: Future<Item=(), Error=Self::Error>;
+    /// Returns address of the remote peer if known
+    fn peer_addr(&self) -> Option<SocketAddr>;
+    /// Converts connection handler into a future
+    fn into_connection(self) -> Self::Future;
+}
+
+/// A connection future with the known address of the remote peer
+///
+/// Wrap your protocol handler into this structure to have peer address
+/// in the log messages emitted by the `Listen`:
+///
+/// ```rust,ignore
+///   listener.incoming()
+///   .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+///   .filter_map(|socket| socket.peer_addr().ok().map(|a| (socket, a)))
+///   .map(|(socket, addr)| Peer::new(addr, Proto::new(socket)))
+///   .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+/// ```
+pub struct Peer<F> {
+    addr: SocketAddr,
+    future: F,
+}
+
+impl<F> Peer<F> {
+    /// Attach peer address to the connection future
+    pub fn new(addr: SocketAddr, future: F) -> Peer<F> {
+        Peer { addr, future }
+    }
+}
+
+impl<F: IntoFuture<Item=()>> Connection for F {
+    type Error = F::Error;
+    type Future = F::Future;
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+    fn into_connection(self) -> F::Future {
+        self.into_future()
+    }
+}
+
+impl<F: IntoFuture<Item=()>> Connection for Peer<F> {
+    type Error = F::Error;
+    type Future = F::Future;
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        Some(self.addr)
+    }
+    fn into_connection(self) -> F::Future {
+        self.future.into_future()
+    }
+}
+
+/// How connection future has been finished
+pub enum Outcome {
+    Done,
+    TimedOut,
+    Panicked,
+}
+
+/// Displays peer address if known
+pub struct PeerName(pub Option<SocketAddr>);
+
+/// A connection future with timeouts applied
+pub struct Conn<F> {
+    peer: Option<SocketAddr>,
+    future: F,
+    max_age: Option<(Duration, Delay)>,
+    idle: Option<(Duration, Delay)>,
+}
+
+impl fmt::Display for PeerName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(addr) => write!(f, "{}", addr),
+            None => f.write_str("<unknown peer>"),
+        }
+    }
+}
+
+impl<F: Future<Item=()>> Conn<F> {
+    pub fn new<C>(conn: C, max_age: Option<Duration>,
+                  idle_timeout: Option<Duration>)
+        -> Conn<F>
+        where C: Connection<Future=F>,
+    {
+        let now = clock::now();
+        Conn {
+            peer: conn.peer_addr(),
+            future: conn.into_connection(),
+            max_age: max_age.map(|t| (t, Delay::new(now + t))),
+            idle: idle_timeout.map(|t| (t, Delay::new(now + t))),
+        }
+    }
+}
+
+impl<F: Future<Item=()>> Future for Conn<F> {
+    type Item = Outcome;
+    type Error = (Option<SocketAddr>, F::Error);
+    fn poll(&mut self) -> Result<Async<Outcome>, Self::Error> {
+        if let Some((age, ref mut timer)) = self.max_age {
+            if timer.poll().expect("delay never fails").is_ready() {
+                info!("Connection from {} reached max age of {:?}, closing",
+                    PeerName(self.peer), age);
+                return Ok(Async::Ready(Outcome::TimedOut));
+            }
+        }
+        if let Some((timeout, ref mut timer)) = self.idle {
+            if timer.poll().expect("delay never fails").is_ready() {
+                info!("Connection from {} is idle for {:?}, closing",
+                    PeerName(self.peer), timeout);
+                return Ok(Async::Ready(Outcome::TimedOut));
+            }
+        }
+        let peer = self.peer;
+        // a panic in a single connection should not crash the whole
+        // listener, and future is dropped anyway, so it's unwind safe
+        let future = &mut self.future;
+        let result = match catch_unwind(AssertUnwindSafe(|| future.poll())) {
+            Ok(result) => result,
+            Err(payload) => {
+                error!("Connection from {} panicked: {}",
+                    PeerName(peer), panic_message(&*payload));
+                return Ok(Async::Ready(Outcome::Panicked));
+            }
+        };
+        match result.map_err(|e| (peer, e))? {
+            Async::Ready(()) => Ok(Async::Ready(Outcome::Done)),
+            Async::NotReady => {
+                // connection future was woken up, so it isn't idle
+                if let Some((timeout, ref mut timer)) = self.idle {
+                    timer.reset(clock::now() + timeout);
+                    // register timer in the current task
+                    if timer.poll().expect("delay never fails").is_ready() {
+                        task::current().notify();
+                    }
+                }
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::{Future, Stream, Async};
+    use futures::future::lazy;
+    use futures::sync::mpsc;
+    use tokio::clock;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::{Delay, Interval};
+
+    use crate::test_util::{Task, Conn, conn, panicking};
+    use crate::traits::ListenExt;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// A connection which is woken up every `period` for `ticks` times
+    fn active(period: Duration, ticks: u64) -> Conn {
+        Box::new(Interval::new_interval(period).take(ticks)
+            .for_each(|_| Ok(()))
+            .map_err(|e| panic!("timer error: {}", e)))
+    }
+
+    #[test]
+    fn max_age() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = rx.listen(10);
+        listen.max_connection_age(ms(30));
+        let stats = listen.stats();
+        tx.unbounded_send(active(ms(5), 1000)).unwrap();
+        tx.unbounded_send(active(ms(5), 2)).unwrap();
+        drop(tx);
+        Runtime::new().unwrap().block_on(listen).unwrap();
+        assert_eq!(stats.completed(), 2);
+        assert_eq!(stats.timed_out(), 1);
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = rx.listen(10);
+        listen.idle_timeout(ms(30));
+        let stats = listen.stats();
+        let (_idle, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        // lives much longer than idle timeout, but isn't idle
+        tx.unbounded_send(active(ms(5), 20)).unwrap();
+        drop(tx);
+        Runtime::new().unwrap().block_on(listen).unwrap();
+        assert_eq!(stats.completed(), 2);
+        assert_eq!(stats.timed_out(), 1);
+        assert_eq!(stats.errored(), 0);
+    }
+
+    #[test]
+    fn timeouts_apply_to_new_connections() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = rx.listen(10);
+        let stats = listen.stats();
+        let (done, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        let listen = runtime.block_on(lazy(move || {
+            listen.poll().unwrap();
+            listen.idle_timeout(ms(10));
+            Ok::<_, ()>(listen)
+        })).unwrap();
+        let (_idle, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        drop(tx);
+        // first connection is idle for longer than timeout, but it was
+        // accepted before the timeout was set
+        let finish = Delay::new(clock::now() + ms(50))
+            .map(move |()| done.send(()).unwrap())
+            .map_err(|e| panic!("timer error: {}", e));
+        runtime.block_on(listen.join(finish)).unwrap();
+        assert_eq!(stats.completed(), 2);
+        assert_eq!(stats.timed_out(), 1);
+    }
+
+    #[test]
+    fn panic_isolated() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = Task::new(rx.listen(10));
+        let stats = listen.get_mut().stats();
+        let (first, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        tx.unbounded_send(panicking()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.panicked(), 1);
+        assert_eq!(stats.active(), 1);
+
+        // listener still accepts and runs connections
+        let (second, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        tx.unbounded_send(panicking()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.active(), 2);
+        first.send(()).unwrap();
+        second.send(()).unwrap();
+        drop(tx);
+        assert_eq!(listen.poll(), Ok(Async::Ready(())));
+        assert_eq!(stats.completed(), 4);
+        assert_eq!(stats.panicked(), 2);
+        assert_eq!(stats.errored(), 0);
+    }
+
+    #[test]
+    fn panic_in_spawned() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = rx.listen(10);
+        listen.spawn_connections();
+        let stats = listen.stats();
+        tx.unbounded_send(panicking()).unwrap();
+        tx.unbounded_send(active(ms(1), 3)).unwrap();
+        drop(tx);
+        Runtime::new().unwrap().block_on(listen).unwrap();
+        assert_eq!(stats.completed(), 2);
+        assert_eq!(stats.panicked(), 1);
+    }
+}
@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+
+use futures::sync::mpsc::UnboundedSender;
+
+
+/// A receiver of connection errors, see `ListenExt::listen_with_errors`
+///
+/// This is implemented for closures `FnMut(Option<SocketAddr>, E)` and for
+/// the sending half of the unbounded channel, so errors can be processed
+/// as a stream in a separate task.
+///
+/// Peer address is passed if connection future is wrapped into `Peer`.
+pub trait ErrorHandler<E> {
+    /// Called when connection future returns an error
+    fn error(&mut self, peer: Option<SocketAddr>, error: E);
+}
+
+/// Error handler of the plain `ListenExt::listen`
+///
+/// It only accepts connection futures with the `()` error, so
+/// errors should be handled (e.g. logged) by the future itself.
+pub struct SwallowErrors;
+
+impl ErrorHandler<()> for SwallowErrors {
+    fn error(&mut self, _peer: Option<SocketAddr>, _error: ()) {}
+}
+
+impl<E, F: FnMut(Option<SocketAddr>, E)> ErrorHandler<E> for F {
+    fn error(&mut self, peer: Option<SocketAddr>, error: E) {
+        self(peer, error)
+    }
+}
+
+impl<E> ErrorHandler<E> for UnboundedSender<(Option<SocketAddr>, E)> {
+    fn error(&mut self, peer: Option<SocketAddr>, error: E) {
+        // if receiver is gone, errors are just ignored
+        self.unbounded_send((peer, error)).ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use futures::{Async, Future};
+    use futures::future::{err, ok, FutureResult};
+    use futures::sync::mpsc;
+
+    use crate::connection::Peer;
+    use crate::test_util::Task;
+    use crate::traits::ListenExt;
+
+    type Conn = Peer<FutureResult<(), &'static str>>;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn closure() {
+        let (tx, rx) = mpsc::unbounded::<FutureResult<(), u32>>();
+        let mut errors = Vec::new();
+        {
+            let listen = rx.listen_with_errors(10, |peer, e| {
+                errors.push((peer, e));
+            });
+            let stats = listen.stats();
+            tx.unbounded_send(err(1)).unwrap();
+            tx.unbounded_send(ok(())).unwrap();
+            tx.unbounded_send(err(2)).unwrap();
+            drop(tx);
+            assert_eq!(listen.wait(), Ok(()));
+            assert_eq!(stats.completed(), 3);
+            assert_eq!(stats.errored(), 2);
+        }
+        errors.sort_unstable();
+        assert_eq!(errors, vec![(None, 1), (None, 2)]);
+    }
+
+    #[test]
+    fn error_stream() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let (listen, errors) = rx.listen_with_error_stream(10);
+        let mut listen = Task::new(listen);
+        let mut errors = Task::new(errors);
+        tx.unbounded_send(Peer::new(addr(1000), err("first"))).unwrap();
+        tx.unbounded_send(Peer::new(addr(1001), ok(()))).unwrap();
+        tx.unbounded_send(Peer::new(addr(1002), err("second"))).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        let mut received = Vec::new();
+        while let Ok(Async::Ready(Some(item))) = errors.poll_next() {
+            received.push(item);
+        }
+        received.sort_unstable();
+        assert_eq!(received, vec![
+            (Some(addr(1000)), "first"),
+            (Some(addr(1002)), "second"),
+        ]);
+
+        // errors are ignored when stream is dropped
+        drop(errors);
+        tx.unbounded_send(Peer::new(addr(1003), err("third"))).unwrap();
+        drop(tx);
+        assert_eq!(listen.poll(), Ok(Async::Ready(())));
+        assert_eq!(listen.get_mut().stats().errored(), 3);
+    }
+
+    #[test]
+    fn swallow_errors() {
+        let (tx, rx) = mpsc::unbounded::<FutureResult<(), ()>>();
+        let listen = rx.listen(10);
+        let stats = listen.stats();
+        tx.unbounded_send(err(())).unwrap();
+        tx.unbounded_send(ok(())).unwrap();
+        drop(tx);
+        assert_eq!(listen.wait(), Ok(()));
+        assert_eq!(stats.completed(), 2);
+        assert_eq!(stats.errored(), 1);
+    }
+}
@@ -0,0 +1,241 @@
+use std::io;
+use std::time::Duration;
+
+use futures::{Future, Stream, Async};
+use futures::stream::FuturesUnordered;
+use tokio::clock;
+use tokio::timer::Delay;
+
+use crate::traits::Peek;
+
+
+/// A structure returned by `ListenExt::first_byte_timeout`
+///
+/// This is a stream which holds accepted sockets until there is some data
+/// to read from them. Sockets that have not sent anything within a timeout
+/// (or are closed by peer) are closed and are never yielded, so they don't
+/// occupy a slot in the `Listen`.
+///
+/// Note: this should not be used for protocols where server sends data
+/// first (like SMTP).
+pub struct FirstByteTimeout<S: Stream> {
+    stream: Option<S>,
+    timeout: Duration,
+    max_pending: usize,
+    pending: FuturesUnordered<WaitData<S::Item>>,
+}
+
+struct WaitData<T> {
+    socket: Option<T>,
+    timer: Delay,
+}
+
+pub fn new<S: Stream>(stream: S, timeout: Duration) -> FirstByteTimeout<S>
+    where S::Item: Peek,
+{
+    FirstByteTimeout {
+        stream: Some(stream),
+        timeout,
+        max_pending: 1000,
+        pending: FuturesUnordered::new(),
+    }
+}
+
+impl<S: Stream> FirstByteTimeout<S> {
+    /// Sets maximum number of sockets waiting for data (default is `1000`)
+    ///
+    /// When there are that many sockets waiting, no new connections are
+    /// accepted, they are held in the kernel backlog.
+    pub fn max_pending(&mut self, max_pending: usize) -> &mut Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Returns number of sockets currently waiting for data
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl<T: Peek> Future for WaitData<T> {
+    type Item = Option<T>;
+    type Error = ();
+    fn poll(&mut self) -> Result<Async<Option<T>>, ()> {
+        let result = self.socket.as_mut().expect("future polled twice")
+            .poll_peek(&mut [0u8]);
+        match result {
+            Ok(Async::Ready(0)) => {
+                debug!("Connection closed before sending any data");
+                Ok(Async::Ready(None))
+            }
+            Ok(Async::Ready(_)) => Ok(Async::Ready(self.socket.take())),
+            Ok(Async::NotReady) => {
+                match self.timer.poll().expect("delay never fails") {
+                    Async::Ready(()) => {
+                        debug!("Timed out waiting for the first byte");
+                        Ok(Async::Ready(None))
+                    }
+                    Async::NotReady => Ok(Async::NotReady),
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                Ok(Async::NotReady)
+            }
+            Err(e) => {
+                debug!("Error waiting for the first byte: {}", e);
+                Ok(Async::Ready(None))
+            }
+        }
+    }
+}
+
+impl<S: Stream> Stream for FirstByteTimeout<S>
+    where S::Item: Peek,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> Result<Async<Option<S::Item>>, S::Error> {
+        loop {
+            while self.pending.len() < self.max_pending {
+                let item = match self.stream {
+                    Some(ref mut stream) => stream.poll()?,
+                    None => break,
+                };
+                match item {
+                    Async::Ready(Some(socket)) => {
+                        self.pending.push(WaitData {
+                            socket: Some(socket),
+                            timer: Delay::new(clock::now() + self.timeout),
+                        });
+                    }
+                    Async::Ready(None) => self.stream = None,
+                    Async::NotReady => break,
+                }
+            }
+            match self.pending.poll() {
+                Ok(Async::Ready(Some(Some(socket)))) => {
+                    return Ok(Async::Ready(Some(socket)));
+                }
+                // socket is closed, let's check next one
+                Ok(Async::Ready(Some(None))) | Err(()) => continue,
+                Ok(Async::Ready(None)) if self.stream.is_none() => {
+                    return Ok(Async::Ready(None));
+                }
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => {
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::time::Duration;
+
+    use futures::{Future, Stream, Async, Poll};
+    use futures::future::lazy;
+    use futures::sync::{mpsc, oneshot};
+    use tokio::clock;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    use crate::test_util::Task;
+    use crate::traits::{ListenExt, Peek};
+
+    /// A socket which receives the specified number of bytes when sender
+    /// is used, and is reset when sender is dropped
+    struct Socket {
+        id: u32,
+        data: oneshot::Receiver<usize>,
+    }
+
+    impl Peek for Socket {
+        fn poll_peek(&mut self, _buf: &mut [u8]) -> Poll<usize, io::Error> {
+            // oneshot receiver keeps the value after it's received
+            self.data.poll().map_err(|_| {
+                io::ErrorKind::ConnectionReset.into()
+            })
+        }
+    }
+
+    fn socket(id: u32) -> (oneshot::Sender<usize>, Socket) {
+        let (tx, rx) = oneshot::channel();
+        (tx, Socket { id, data: rx })
+    }
+
+    fn ids<S: Stream<Item=Socket, Error=()>>(task: &mut Task<S>)
+        -> Vec<u32>
+    {
+        let mut result = Vec::new();
+        loop {
+            match task.poll_next() {
+                Ok(Async::Ready(Some(sock))) => result.push(sock.id),
+                Ok(Async::NotReady) => return result,
+                _ => panic!("stream is finished"),
+            }
+        }
+    }
+
+    #[test]
+    fn first_byte() {
+        let mut runtime = Runtime::new().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let mut stream = Task::new(
+            rx.first_byte_timeout(Duration::from_millis(20)));
+        let (data, a) = socket(1);
+        let (closed, b) = socket(2);
+        let (reset, c) = socket(3);
+        let (_silent, d) = socket(4);
+        for sock in [a, b, c, d] {
+            tx.unbounded_send(sock).unwrap();
+        }
+        runtime.block_on(lazy(|| {
+            assert_eq!(ids(&mut stream), Vec::<u32>::new());
+            assert_eq!(stream.get_mut().pending(), 4);
+            data.send(10).unwrap();
+            closed.send(0).unwrap();
+            drop(reset);
+            assert!(stream.woken());
+            assert_eq!(ids(&mut stream), vec![1]);
+            assert_eq!(stream.get_mut().pending(), 1);
+            Ok::<(), ()>(())
+        })).unwrap();
+        let deadline = clock::now() + Duration::from_millis(40);
+        runtime.block_on(Delay::new(deadline)).unwrap();
+        runtime.block_on(lazy(|| {
+            // silent socket is closed by timeout
+            assert!(stream.woken());
+            assert_eq!(ids(&mut stream), Vec::<u32>::new());
+            assert_eq!(stream.get_mut().pending(), 0);
+            drop(tx);
+            match stream.poll_next() {
+                Ok(Async::Ready(None)) => {}
+                _ => panic!("stream is not finished"),
+            }
+            Ok::<(), ()>(())
+        })).unwrap();
+    }
+
+    #[test]
+    fn max_pending() {
+        Runtime::new().unwrap().block_on(lazy(|| {
+            let (tx, rx) = mpsc::unbounded();
+            let mut stream = rx.first_byte_timeout(Duration::new(10, 0));
+            stream.max_pending(1);
+            let mut stream = Task::new(stream);
+            let (first, a) = socket(1);
+            let (second, b) = socket(2);
+            tx.unbounded_send(a).unwrap();
+            tx.unbounded_send(b).unwrap();
+            // second socket is ready, but it's left in the backlog
+            second.send(1).unwrap();
+            assert_eq!(ids(&mut stream), Vec::<u32>::new());
+            assert_eq!(stream.get_mut().pending(), 1);
+            first.send(1).unwrap();
+            assert_eq!(ids(&mut stream), vec![1, 2]);
+            Ok::<(), ()>(())
+        })).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+
+use futures::Poll;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::traits::{PeerAddr, Peek};
+
+
+/// A socket with a guard attached
+///
+/// This is an item type of the combinators which track accepted
+/// connections (like `ListenExt::per_ip_limit`). The guard holds a
+/// slot until the socket is dropped, so a connection is accounted until
+/// its protocol handler finishes (or drops the socket in any other way).
+///
+/// The wrapper implements `AsyncRead` and `AsyncWrite`, so it can be
+/// passed directly to protocol implementations.
+pub struct Guarded<T, G> {
+    io: T,
+    guard: G,
+}
+
+impl<T, G> Guarded<T, G> {
+    pub(crate) fn new(io: T, guard: G) -> Guarded<T, G> {
+        Guarded { io, guard }
+    }
+
+    /// Returns a reference to the underlying socket
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the underlying socket
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Returns a reference to the guard
+    pub fn guard(&self) -> &G {
+        &self.guard
+    }
+}
+
+impl<T: PeerAddr, G> PeerAddr for Guarded<T, G> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.io.peer_addr()
+    }
+}
+
+impl<T: Peek, G> Peek for Guarded<T, G> {
+    fn poll_peek(&mut self, buf: &mut [u8]) -> Poll<usize, io::Error> {
+        self.io.poll_peek(buf)
+    }
+}
+
+impl<T: Read, G> Read for Guarded<T, G> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl<T: Write, G> Write for Guarded<T, G> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead, G> AsyncRead for Guarded<T, G> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite, G> AsyncWrite for Guarded<T, G> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
@@ -1,7 +1,7 @@
 //!  A library that allows to listen network sockets with proper resource
 //!  limits and error handling.
 //!
-//!  Library constists of three things:
+//!  Library consists of these things:
 //!
 //!  * [`sleep_on_error`][1] -- filters `Stream` of accepted sockets for
 //!    errors.  Simple errors like `ConnectionReset` are just ignored. Severe
@@ -9,15 +9,36 @@
 //!    the delay specified, effectively allowing other connections to be
 //!    processed and release resources for new ones.
 //!    [Replaces code like this][2].
-//!  * [`listen`][3] -- iterates over a stream using [`buffer_unordered`][4]
+//!  * [`listen`][3] -- iterates over a stream running at most
+//!    `max_connections` futures at once, like [`buffer_unordered`][4]
 //!    combinator. It also suppresses errors in futures (because otherwise
 //!    every connection error would shut down the whole stream). And returns
 //!    `ForEach`-like future, you can `run()` or combine with other futures.
+//!    Live connection counters are available via [`Listen::stats`][6] and
+//!    the limit can be changed at runtime via [`Listen::limit`][7].
+//!    Several listeners can also draw from a single [`SharedLimit`].
+//!    On multi-threaded runtime connections can be spawned as separate
+//!    tasks using [`Listen::spawn_connections`][11].
 //!    [Stands for code like this][5].
 //!  * [`BindMany`] allows to bind to list of addresses and update that list
 //!    (i.e. allow configuration reload), resulting into a single stream with
 //!    accepted sockets. This a good idea to use it with [abstract-ns] to
 //!    resolve list of names to addresses and keep them updated.
+//!    Sockets can be closed while listener is at the limit, so load
+//!    balancers fail over to other nodes, see [`Saturation`].
+//!  * [`per_ip_limit`][8] -- limits number of simultaneous connections
+//!    from a single client.
+//!  * [`rate_limit`][9] -- limits the rate of accepted connections, so
+//!    floods of new connections wait in the kernel backlog.
+//!  * [`first_byte_timeout`][10] -- closes connections that don't send
+//!    anything, before they take a slot in the listener.
+//!  * [`overload`][12] -- rejects connections over the limit (e.g. with
+//!    a canned response) instead of keeping clients in the backlog.
+//!
+//!  All of these are for futures 0.1 and tokio 0.1 and are available with
+//!  the `legacy` feature (enabled by default). Counterparts of
+//!  `sleep_on_error`, `listen` and `BindMany` for `std::future` and
+//!  tokio 1.x are in the [`std_future`] module.
 //!
 //!  [1]: trait.ListenExt.html#method.sleep_on_error
 //!  TODO: Update
@@ -26,8 +47,19 @@
 //!  [4]: https://docs.rs/futures/0.1.11/futures/stream/trait.Stream.html#method.buffer_unordered
 //!  TODO: Update
 //!  [5]: https://git.io/vy9vi#L56-L59
+//!  [6]: struct.Listen.html#method.stats
+//!  [7]: struct.Listen.html#method.limit
+//!  [8]: trait.ListenExt.html#method.per_ip_limit
+//!  [9]: trait.ListenExt.html#method.rate_limit
+//!  [10]: trait.ListenExt.html#method.first_byte_timeout
+//!  [11]: struct.Listen.html#method.spawn_connections
+//!  [12]: trait.ListenExt.html#method.overload
 //!  [abstract-ns]: https://docs.rs/abstract-ns
 //!  [`BindMany`]: struct.BindMany.html
+//!  [`SharedLimit`]: struct.SharedLimit.html
+//!  [`Saturation`]: struct.Saturation.html
+//!  [`std_future`]: std_future/index.html
+//!  [`Peer`]: struct.Peer.html
 //!
 //!  # Example
 //!
@@ -44,20 +76,19 @@
 //!        .map(move |mut socket| {
 //!             // Your future is here:
 //!             Proto::new(socket)
-//!             // Errors should not pass silently
-//!             // common idea is to log them
-//!             .map(|result| {
-//!                 match result {
-//!                     Ok(_) => (),
-//!                     Err(e) => error!("Conn error: {}", e),
-//!                 }
-//!             })
-//!             .map_err(|_| ())
 //!        })
-//!        .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+//!        // Errors should not pass silently
+//!        // common idea is to log them
+//!        .listen_with_errors(MAX_SIMULTANEOUS_CONNECTIONS, |_peer, e| {
+//!             error!("Conn error: {}", e)
+//!        })
 //!    ).unwrap(); // stream doesn't end in this case
 //!  ```
 //!
+//!  Alternatively, use [`listen`][3] if connection future handles errors by
+//!  itself (i.e. its error type is `()`). Wrap connection future into
+//!  [`Peer`] to get peer address passed to the error handler.
+//!
 //!  # Example With Listener Shutdown
 //!
 //!  Because tk-listen works as a combinator trait, you can easily add
@@ -71,17 +102,10 @@
 //!        .map(move |mut socket| {
 //!             // Your future is here:
 //!             Proto::new(socket)
-//!             // Errors should not pass silently
-//!             // common Idea is to log them
-//!             .map(|result| {
-//!                 match result {
-//!                     Ok(_) => (),
-//!                     Err(e) => error!("Conn error: {}", e),
-//!                 }
-//!             })
-//!             .map_err(|_| ())
 //!        })
-//!        .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+//!        .listen_with_errors(MAX_SIMULTANEOUS_CONNECTIONS, |_peer, e| {
+//!             error!("Conn error: {}", e)
+//!        })
 //!        .select(|_| rx)
 //!    )
 //!  ```
@@ -93,19 +117,77 @@
 //!  immediately. It's also possible to stop accepting by closing original
 //!  stream (e.g. using `take_while`) and wait until all connections
 //!  shutdown gracefully.
+//!
+//!  # Example With Graceful Shutdown
+//!
+//!  To let connections finish current request, pass a [`Token`] to each
+//!  of them:
+//!
+//!  ```rust,ignore
+//!    let shutdown = Shutdown::new();
+//!    lp.run(
+//!        listener.incoming()
+//!        .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+//!        .with_shutdown(&shutdown)
+//!        .map(move |(socket, token)| {
+//!             // Protocol should stop reading new requests when token
+//!             // is resolved
+//!             Proto::new(socket, token)
+//!        })
+//!        .listen_with_errors(MAX_SIMULTANEOUS_CONNECTIONS, |_peer, e| {
+//!             error!("Conn error: {}", e)
+//!        })
+//!    )
+//!  ```
+//!
+//!  Calling `shutdown.shutdown()` stops accepting connections and notifies
+//!  every token. The future above resolves when all connections are closed.
+//!
+//!  [`Token`]: struct.Token.html
 #![warn(missing_docs)]
 
-extern crate futures;
-extern crate tokio;
-
 #[macro_use] extern crate log;
 
-mod bind;
-mod traits;
-mod sleep_on_error;
-mod listen;
+pub mod std_future;
+
+mod peer;
+mod util;
+
+#[cfg(feature = "legacy")] mod bind;
+#[cfg(feature = "legacy")] mod connection;
+#[cfg(feature = "legacy")] mod errors;
+#[cfg(feature = "legacy")] mod guarded;
+#[cfg(feature = "legacy")] mod per_ip;
+#[cfg(feature = "legacy")] mod first_byte;
+#[cfg(feature = "legacy")] mod overload;
+#[cfg(feature = "legacy")] mod rate_limit;
+#[cfg(feature = "legacy")] mod traits;
+#[cfg(feature = "legacy")] mod sleep_on_error;
+#[cfg(feature = "legacy")] mod listen;
+#[cfg(feature = "legacy")] mod limit;
+#[cfg(feature = "legacy")] mod shared_limit;
+#[cfg(feature = "legacy")] mod saturation;
+#[cfg(feature = "legacy")] mod shutdown;
+#[cfg(feature = "legacy")] mod spawn;
+#[cfg(feature = "legacy")] mod stats;
+#[cfg(all(test, feature = "legacy"))] mod test_util;
 
-pub use traits::ListenExt;
-pub use sleep_on_error::SleepOnError;
-pub use listen::Listen;
-pub use bind::BindMany;
+#[cfg(feature = "legacy")]
+pub use crate::{
+    traits::{ListenExt, PeerAddr, Peek, ErrorSender, ErrorStream},
+    sleep_on_error::SleepOnError,
+    listen::Listen,
+    bind::BindMany,
+    shutdown::{Shutdown, Token, WithShutdown},
+    stats::Stats,
+    connection::{Connection, Peer},
+    errors::{ErrorHandler, SwallowErrors},
+    limit::Limit,
+    shared_limit::SharedLimit,
+    saturation::Saturation,
+    guarded::Guarded,
+    per_ip::{PerIpLimit, IpGuard},
+    rate_limit::{RateLimit, PeerRateLimit},
+    first_byte::FirstByteTimeout,
+    overload::{Overload, OverloadGuard, Reject, Close, Response, Respond},
+};
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::task::AtomicTask;
+
+
+struct Inner {
+    value: AtomicUsize,
+    task: AtomicTask,
+}
+
+/// A handle to change connection limit of a running `Listen`
+///
+/// Returned by `Listen::limit`. It's cheap to clone and can be used from
+/// any thread or task.
+///
+/// Raising the limit makes listener accept new connections immediately.
+/// Lowering it never closes existing connections, listener just stops
+/// accepting new ones until number of active connections drops below
+/// the new value.
+#[derive(Clone)]
+pub struct Limit {
+    inner: Arc<Inner>,
+}
+
+impl Limit {
+    pub(crate) fn new(value: usize) -> Limit {
+        Limit {
+            inner: Arc::new(Inner {
+                value: AtomicUsize::new(value),
+                task: AtomicTask::new(),
+            }),
+        }
+    }
+
+    /// Registers current task to be woken up when limit is changed
+    pub(crate) fn register(&self) {
+        self.inner.task.register();
+    }
+
+    /// Returns current maximum number of connections
+    pub fn get(&self) -> usize {
+        self.inner.value.load(Ordering::SeqCst)
+    }
+
+    /// Changes maximum number of connections
+    pub fn set(&self, value: usize) {
+        let old = self.inner.value.swap(value, Ordering::SeqCst);
+        if old != value {
+            info!("Connection limit changed from {} to {}", old, value);
+            self.inner.task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Async;
+    use futures::sync::mpsc;
+
+    use crate::test_util::{Task, Conn, conn};
+    use crate::traits::ListenExt;
+
+    #[test]
+    fn set_wakes_listener() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = Task::new(rx.listen(1));
+        let stats = listen.get_mut().stats();
+        let limit = listen.get_mut().limit();
+        let (first, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        let (second, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.active(), 1);
+        listen.woken();
+
+        limit.set(2);
+        assert_eq!(limit.get(), 2);
+        assert!(listen.woken());
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.active(), 2);
+
+        // setting the same value doesn't wake listener up
+        limit.set(2);
+        assert!(!listen.woken());
+
+        // lowering the limit doesn't close connections
+        limit.set(1);
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.active(), 2);
+        let (_third, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        first.send(()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.active(), 1);
+        second.send(()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.active(), 1);
+        assert_eq!(stats.accepted(), 3);
+    }
+}
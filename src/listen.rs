@@ -1,86 +1,325 @@
-use std::marker::PhantomData;
+use std::time::Duration;
 
-use futures::{Stream, Future, IntoFuture, Async};
-use futures::stream::BufferUnordered;
+use tokio::clock;
+use tokio::timer::Delay;
 
+use futures::{Stream, Future, Async};
+use futures::stream::FuturesUnordered;
 
-/// This is basically `.then(|_| Ok(()))`, but we can't name a closure
-struct SwallowErrors<F: Future<Item=(), Error=()>,  E>(F, PhantomData<E>);
-
-/// This is basically `map(|f| f.then(|_| Ok(())))`,
-/// but we can't name a closure
-struct MapSwallowErrors<S: Stream>(S)
-    where S::Item: IntoFuture<Item=(), Error=()>;
-
+use crate::connection::{Connection, Conn, Outcome};
+use crate::errors::{ErrorHandler, SwallowErrors};
+use crate::limit::Limit;
+use crate::rate_limit;
+use crate::saturation::Saturation;
+use crate::shared_limit::{SharedLimit, Share};
+use crate::spawn::Spawned;
+use crate::stats::Stats;
 
 
 /// A structure returned by `ListenExt::listen`
 ///
 /// This is a future that returns when incoming stream has been closed and
-/// all connections (futures) have been processed. It uses `BufferUnordered`
-/// inside to do heavy lifting.
-pub struct Listen<S: Stream>
-    where S::Item: IntoFuture<Item=(), Error=()>,
+/// all connections (futures) have been processed. Errors of the connection
+/// futures are passed to the error handler (and counted in `Stats`)
+/// instead of being returned, because otherwise every connection error
+/// would shut down the whole listener. For the same reason panics in
+/// connection futures are caught, logged and the connection is dropped.
+pub struct Listen<S: Stream, H=SwallowErrors>
+    where S::Item: Connection,
+          H: ErrorHandler<<S::Item as Connection>::Error>,
 {
-    buffer: BufferUnordered<MapSwallowErrors<S>>,
+    stream: Option<S>,
+    futures: FuturesUnordered<Conn<<S::Item as Connection>::Future>>,
+    spawned: Option<Spawned<<S::Item as Connection>::Future>>,
+    max_connections: Limit,
+    at_limit: bool,
+    stats: Stats,
+    share: Option<Share>,
+    saturation: Option<Saturation>,
+    saturation_timer: Option<Delay>,
+    held: Option<S::Item>,
+    max_age: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    errors: H,
 }
 
 pub fn new<S: Stream>(stream: S, limit: usize) -> Listen<S>
-    where S::Item: IntoFuture<Item=(), Error=()>,
+    where S::Item: Connection<Error=()>,
+{
+    with_errors(stream, limit, SwallowErrors)
+}
+
+pub fn with_errors<S: Stream, H>(stream: S, limit: usize, errors: H)
+    -> Listen<S, H>
+    where S::Item: Connection,
+          H: ErrorHandler<<S::Item as Connection>::Error>,
 {
     Listen {
-        buffer: MapSwallowErrors(stream).buffer_unordered(limit),
+        stream: Some(stream),
+        futures: FuturesUnordered::new(),
+        spawned: None,
+        max_connections: Limit::new(limit),
+        at_limit: false,
+        stats: Stats::new(),
+        share: None,
+        saturation: None,
+        saturation_timer: None,
+        held: None,
+        max_age: None,
+        idle_timeout: None,
+        errors,
     }
 }
 
-impl<S: Stream> Future for Listen<S>
-    where S::Item: IntoFuture<Item=(), Error=()>,
+impl<S: Stream, H> Listen<S, H>
+    where S::Item: Connection,
+          H: ErrorHandler<<S::Item as Connection>::Error>,
 {
-    type Item = ();
-    type Error = S::Error;
-    fn poll(&mut self) -> Result<Async<()>, S::Error> {
+    /// Returns a handle to live statistics of this listener
+    pub fn stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    /// Returns a handle that allows to change `max_connections` at runtime
+    pub fn limit(&self) -> Limit {
+        self.max_connections.clone()
+    }
+
+    /// Makes this listener use slots from the shared limit
+    ///
+    /// Each connection of this listener takes a slot in the `limit`, which
+    /// might be shared with other listeners. `reserved` number of slots
+    /// can't be used by other listeners. Should be called before the
+    /// listener is started. See `SharedLimit` for more info.
+    pub fn shared_limit(&mut self, limit: &SharedLimit, reserved: usize)
+        -> &mut Self
+    {
+        let share = limit.share(reserved);
+        // account connections that are already running, if any
+        share.occupy(self.running());
+        self.share = Some(share);
+        self
+    }
+
+    /// Reports reaching the limit to `BindMany`, see `Saturation`
+    ///
+    /// When listener has been at the limit for the time configured in
+    /// `saturation`, it polls the stream once (so `BindMany` can close
+    /// listening sockets). If some intermediate stage yields a connection
+    /// at this point, it's held until there is a free slot.
+    pub fn saturation(&mut self, saturation: &Saturation) -> &mut Self {
+        saturation.set_at_limit(self.at_limit);
+        self.saturation = Some(saturation.clone());
+        self
+    }
+
+    /// Sets maximum lifetime of a connection
+    ///
+    /// Connection future is dropped (and so connection is closed) when
+    /// the time elapses, regardless of whether it does anything or not.
+    /// Applies to connections accepted after the call.
+    pub fn max_connection_age(&mut self, age: Duration) -> &mut Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Sets the inactivity timeout of a connection
+    ///
+    /// Connection future is dropped if it hasn't been woken up for the
+    /// specified time. Usually a future is woken up on I/O (or its own
+    /// timers), so this is a timeout for a connection that neither sends
+    /// nor receives anything. Applies to connections accepted after
+    /// the call.
+    ///
+    /// Wrap connection future into `Peer` to have peer address logged
+    /// when timeout happens.
+    pub fn idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Runs every connection as a separate task on the default executor
+    ///
+    /// By default all connections are polled inside the task of the
+    /// `Listen` future, so they are all processed by a single thread even
+    /// on the multi-threaded runtime. After this call connections are
+    /// spawned with `tokio::spawn`, so listener must be run within tokio
+    /// runtime. `max_connections`, timeouts, statistics and error handling
+    /// work the same, as listener is notified when each task finishes.
+    ///
+    /// Dropping the listener drops spawned connections too. Applies to
+    /// connections accepted after the call.
+    pub fn spawn_connections(&mut self) -> &mut Self
+        where <S::Item as Connection>::Future: Send + 'static,
+              <S::Item as Connection>::Error: Send + 'static,
+    {
+        if self.spawned.is_none() {
+            self.spawned = Some(Spawned::new());
+        }
+        self
+    }
+
+    /// Number of connections that are currently running
+    fn running(&self) -> usize {
+        self.futures.len() +
+            self.spawned.as_ref().map(|s| s.len()).unwrap_or(0)
+    }
+
+    /// Pulls new connections from the stream while below the limit
+    fn accept(&mut self) -> Result<(), S::Error> {
+        self.max_connections.register();
+        let max_connections = self.max_connections.get();
+        let mut at_limit = false;
         loop {
-            match self.buffer.poll()? {
-                // Some future just finished, let's check for next one
-                Async::Ready(Some(())) => continue,
-                // No future ready
-                Async::NotReady => return Ok(Async::NotReady),
-                // Stream is done
-                Async::Ready(None) => return Ok(Async::Ready(())),
+            if self.running() >= max_connections {
+                at_limit = true;
+                if let Some(ref share) = self.share {
+                    share.pass();
+                }
+                break;
+            }
+            if self.stream.is_none() && self.held.is_none() {
+                if let Some(ref share) = self.share {
+                    share.pass();
+                }
+                break;
+            }
+            // a slot is taken only when there is a connection, so idle
+            // listeners don't wake up each other
+            if let Some(ref share) = self.share {
+                if !share.available() {
+                    at_limit = true;
+                    break;
+                }
+            }
+            let result = match self.held.take() {
+                Some(item) => Ok(Async::Ready(Some(item))),
+                None => self.stream.as_mut().expect("stream is open").poll(),
+            };
+            if let Ok(Async::Ready(Some(f))) = result {
+                if let Some(ref share) = self.share {
+                    // slot was taken by another listener in the meantime
+                    if !share.acquire() {
+                        self.held = Some(f);
+                        at_limit = true;
+                        break;
+                    }
+                }
+                let conn = Conn::new(f, self.max_age, self.idle_timeout);
+                match self.spawned {
+                    Some(ref mut spawned) => spawned.spawn(conn),
+                    None => self.futures.push(conn),
+                }
+                self.stats.accepted_one();
+                continue;
+            }
+            if let Async::Ready(None) = result? {
+                self.stream = None;
+            }
+            break;
+        }
+        if at_limit != self.at_limit {
+            self.at_limit = at_limit;
+            self.stats.set_at_limit(at_limit);
+            if let Some(ref saturation) = self.saturation {
+                saturation.set_at_limit(at_limit);
             }
         }
+        if at_limit {
+            self.poll_saturation()?;
+        } else {
+            self.saturation_timer = None;
+        }
+        Ok(())
     }
-}
 
-impl<F: Future<Item=(), Error=()>, E> Future for SwallowErrors<F, E> {
-    type Item = ();
-    type Error = E;  // actually void
+    /// Polls the stream once when limit is reached for long enough
+    fn poll_saturation(&mut self) -> Result<(), S::Error> {
+        let deadline = match self.saturation {
+            Some(ref saturation) => saturation.deadline(),
+            None => None,
+        };
+        if let Some(deadline) = deadline {
+            let now = clock::now();
+            if now < deadline &&
+                !rate_limit::wait(&mut self.saturation_timer,
+                                  now, deadline - now)
+            {
+                return Ok(());
+            }
+            if self.held.is_some() {
+                return Ok(());
+            }
+            let item = match self.stream {
+                Some(ref mut stream) => stream.poll()?,
+                None => return Ok(()),
+            };
+            match item {
+                Async::Ready(Some(item)) => self.held = Some(item),
+                Async::Ready(None) => self.stream = None,
+                Async::NotReady => {}
+            }
+        }
+        Ok(())
+    }
 
-    #[inline(always)]
-    fn poll(&mut self) -> Result<Async<F::Item>, E> {
-        match self.0.poll() {
-            Ok(x) => Ok(x),
-            Err(()) => Ok(Async::Ready(())),
+    fn completed(&mut self, outcome: Option<Outcome>) {
+        self.stats.completed_one(outcome);
+        if let Some(ref share) = self.share {
+            share.release(1);
         }
     }
 }
 
-impl<S: Stream> Stream for MapSwallowErrors<S>
-    where S::Item: IntoFuture<Item=(), Error=()>,
+impl<S: Stream, H> Future for Listen<S, H>
+    where S::Item: Connection,
+          H: ErrorHandler<<S::Item as Connection>::Error>,
 {
-    type Item = SwallowErrors<<S::Item as IntoFuture>::Future, S::Error>;
+    type Item = ();
     type Error = S::Error;
-
-    #[inline(always)]
-    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, S::Error> {
-        match self.0.poll()? {
-            Async::Ready(None) => Ok(Async::Ready(None)),
-            Async::Ready(Some(f)) => {
-                Ok(Async::Ready(Some(
-                    SwallowErrors(f.into_future(), PhantomData)
-                )))
+    fn poll(&mut self) -> Result<Async<()>, S::Error> {
+        loop {
+            self.accept()?;
+            let result = match (self.futures.poll(), &mut self.spawned) {
+                (Ok(Async::NotReady), &mut Some(ref mut spawned)) |
+                (Ok(Async::Ready(None)), &mut Some(ref mut spawned))
+                => spawned.poll(),
+                (result, _) => result,
+            };
+            match result {
+                // Some future just finished, let's check for next one
+                Ok(Async::Ready(Some(outcome))) => {
+                    self.completed(Some(outcome))
+                }
+                Err((peer, e)) => {
+                    self.errors.error(peer, e);
+                    self.completed(None);
+                }
+                // No future ready
+                Ok(Async::NotReady) | Ok(Async::Ready(None)) => {
+                    if self.stream.is_none() && self.held.is_none() &&
+                        self.running() == 0
+                    {
+                        // Stream is done
+                        return Ok(Async::Ready(()));
+                    }
+                    return Ok(Async::NotReady);
+                }
             }
-            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<S: Stream, H> Drop for Listen<S, H>
+    where S::Item: Connection,
+          H: ErrorHandler<<S::Item as Connection>::Error>,
+{
+    fn drop(&mut self) {
+        self.stats.dropped(self.running());
+        // slots of shared limit are returned when share is dropped
+        self.stats.set_at_limit(false);
+        if let Some(ref saturation) = self.saturation {
+            saturation.set_at_limit(false);
         }
     }
 }
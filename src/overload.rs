@@ -0,0 +1,620 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use futures::{Future, IntoFuture, Stream, Async};
+use futures::future::{self, FutureResult};
+use futures::task::AtomicTask;
+use tokio::clock;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Delay;
+
+use crate::guarded::Guarded;
+use crate::limit::Limit;
+
+
+struct Inner {
+    active: AtomicUsize,
+    rejecting: AtomicUsize,
+    task: AtomicTask,
+}
+
+/// A way to reject connections over the limit, see `ListenExt::overload`
+///
+/// This is implemented for `Close`, `Response` and for any function
+/// that accepts a socket and returns a future.
+pub trait Reject<T> {
+    /// A future that handles rejected connection
+    type Future: Future<Item=(), Error=()>;
+    /// Starts rejecting the connection
+    fn reject(&mut self, socket: T) -> Self::Future;
+}
+
+/// A reject policy that closes connections immediately
+pub struct Close;
+
+/// A reject policy that writes a canned response and closes connection
+///
+/// Response is written as is, so it must be a complete message of your
+/// protocol (e.g. HTTP 503 with `Connection: close`), for HTTP there is
+/// a ready-made `Response::http_unavailable`.
+///
+/// After the response is written, socket is shut down and the rest of the
+/// incoming data (i.e. the request) is read and discarded until client
+/// closes connection. Otherwise, closing a socket with unread data resets
+/// the connection and client might not receive the response at all.
+/// Whole process is limited by the `timeout` (default is 10 seconds).
+pub struct Response {
+    data: Arc<[u8]>,
+    timeout: Duration,
+}
+
+/// A future returned by `Response` that writes the data
+pub struct Respond<T> {
+    socket: T,
+    data: Arc<[u8]>,
+    pos: usize,
+    shutdown: bool,
+    timer: Delay,
+}
+
+/// A guard that holds a slot of the `Overload` stream
+pub struct OverloadGuard {
+    inner: Arc<Inner>,
+}
+
+/// A rejection future running in its own task
+struct Rejection<F> {
+    future: F,
+    inner: Arc<Inner>,
+}
+
+/// A structure returned by `ListenExt::overload`
+///
+/// This is a stream which counts active connections (until the socket,
+/// wrapped into a `Guarded`, is dropped) and when there are already
+/// `max_connections` of them, keeps accepting and passes new sockets to
+/// the reject policy instead of yielding them. So clients get immediate
+/// feedback instead of waiting in the kernel backlog.
+///
+/// At most `max_rejecting` (default is `100`) connections are rejected at
+/// once, when there are more, the underlying stream isn't polled until
+/// some of them are finished.
+///
+/// Every rejection is spawned with `tokio::spawn` (so the stream must be
+/// polled within tokio runtime), because rejected clients should get
+/// a response even when nobody polls this stream (i.e. when `listen` is
+/// at its limit). Rejections are not cancelled when the stream is dropped,
+/// use `Response::timeout` to limit their lifetime.
+///
+/// Note: limit of the `listen` must be larger than `max_connections`,
+/// because it doesn't poll the stream when its own limit is reached:
+///
+/// ```rust,ignore
+///   listener.incoming()
+///   .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+///   .overload(MAX_CONNECTIONS, Response::http_unavailable(RETRY_AFTER))
+///   .map(|socket| Proto::new(socket))
+///   .listen(2*MAX_CONNECTIONS)
+/// ```
+pub struct Overload<S: Stream, R: Reject<S::Item>> {
+    stream: Option<S>,
+    reject: R,
+    limit: Limit,
+    max_rejecting: usize,
+    inner: Arc<Inner>,
+}
+
+pub fn new<S: Stream, R>(stream: S, max_connections: usize, reject: R)
+    -> Overload<S, R>
+    where R: Reject<S::Item>,
+{
+    Overload {
+        stream: Some(stream),
+        reject,
+        limit: Limit::new(max_connections),
+        max_rejecting: 100,
+        inner: Arc::new(Inner {
+            active: AtomicUsize::new(0),
+            rejecting: AtomicUsize::new(0),
+            task: AtomicTask::new(),
+        }),
+    }
+}
+
+impl Response {
+    /// Create a policy that writes `data` to every rejected connection
+    pub fn new<D: Into<Vec<u8>>>(data: D) -> Response {
+        Response {
+            data: Arc::from(data.into()),
+            timeout: Duration::new(10, 0),
+        }
+    }
+
+    /// Create a policy that responds with `503 Service Unavailable`
+    ///
+    /// Response is a minimal HTTP/1.1 message with `Retry-After` header
+    /// (in seconds, rounded up) and `Connection: close`.
+    pub fn http_unavailable(retry_after: Duration) -> Response {
+        let seconds = retry_after.as_secs() +
+            if retry_after.subsec_nanos() > 0 { 1 } else { 0 };
+        let body = "Service Unavailable\n";
+        Response::new(format!(
+            "HTTP/1.1 503 Service Unavailable\r\n\
+             Retry-After: {}\r\n\
+             Connection: close\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Length: {}\r\n\
+             \r\n\
+             {}", seconds, body.len(), body))
+    }
+
+    /// Sets timeout for writing the response (and closing connection)
+    ///
+    /// Connection is dropped when timeout expires, so slow clients can't
+    /// hold slots of `max_rejecting` for long.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl<T> Reject<T> for Close {
+    type Future = FutureResult<(), ()>;
+    fn reject(&mut self, _socket: T) -> FutureResult<(), ()> {
+        future::ok(())
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Reject<T> for Response {
+    type Future = Respond<T>;
+    fn reject(&mut self, socket: T) -> Respond<T> {
+        Respond {
+            socket,
+            data: self.data.clone(),
+            pos: 0,
+            shutdown: false,
+            timer: Delay::new(clock::now() + self.timeout),
+        }
+    }
+}
+
+impl<T, F, R> Reject<T> for F
+    where F: FnMut(T) -> R,
+          R: IntoFuture<Item=(), Error=()>,
+{
+    type Future = R::Future;
+    fn reject(&mut self, socket: T) -> R::Future {
+        (self)(socket).into_future()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Future for Respond<T> {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Result<Async<()>, ()> {
+        if self.timer.poll().expect("delay never fails").is_ready() {
+            debug!("Timed out writing overload response");
+            return Ok(Async::Ready(()));
+        }
+        while self.pos < self.data.len() {
+            match self.socket.poll_write(&self.data[self.pos..]) {
+                Ok(Async::Ready(0)) => {
+                    debug!("Connection closed while writing response");
+                    return Ok(Async::Ready(()));
+                }
+                Ok(Async::Ready(n)) => self.pos += n,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    debug!("Error writing overload response: {}", e);
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+        if !self.shutdown {
+            match self.socket.shutdown() {
+                Ok(Async::Ready(())) => self.shutdown = true,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    debug!("Error closing rejected connection: {}", e);
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+        // discard the request, so connection isn't reset on close
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.socket.poll_read(&mut buf) {
+                Ok(Async::Ready(0)) => return Ok(Async::Ready(())),
+                Ok(Async::Ready(_)) => continue,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => {
+                    debug!("Error reading from rejected connection: {}", e);
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    }
+}
+
+impl<S: Stream, R: Reject<S::Item>> Overload<S, R> {
+    /// Sets maximum number of connections being rejected at once
+    pub fn max_rejecting(&mut self, max_rejecting: usize) -> &mut Self {
+        self.max_rejecting = max_rejecting;
+        self
+    }
+
+    /// Returns a handle that allows to change `max_connections` at runtime
+    pub fn limit(&self) -> Limit {
+        self.limit.clone()
+    }
+
+    /// Returns number of active (not rejected) connections
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+
+    /// Returns number of connections currently being rejected
+    pub fn rejecting(&self) -> usize {
+        self.inner.rejecting.load(Ordering::SeqCst)
+    }
+}
+
+impl<S: Stream, R: Reject<S::Item>> Stream for Overload<S, R>
+    where R::Future: Send + 'static,
+{
+    type Item = Guarded<S::Item, OverloadGuard>;
+    type Error = S::Error;
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, S::Error> {
+        loop {
+            self.limit.register();
+            self.inner.task.register();
+            let at_limit = self.active() >= self.limit.get();
+            if at_limit && self.rejecting() >= self.max_rejecting {
+                return Ok(Async::NotReady);
+            }
+            let item = match self.stream {
+                Some(ref mut stream) => stream.poll()?,
+                None => return Ok(Async::Ready(None)),
+            };
+            match item {
+                Async::Ready(Some(socket)) if at_limit => {
+                    debug!("Too many connections, rejecting");
+                    self.inner.rejecting.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(Rejection {
+                        future: self.reject.reject(socket),
+                        inner: self.inner.clone(),
+                    });
+                }
+                Async::Ready(Some(socket)) => {
+                    self.inner.active.fetch_add(1, Ordering::SeqCst);
+                    let guard = OverloadGuard { inner: self.inner.clone() };
+                    return Ok(Async::Ready(Some(Guarded::new(socket, guard))));
+                }
+                Async::Ready(None) => self.stream = None,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl<F: Future<Item=(), Error=()>> Future for Rejection<F> {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Result<Async<()>, ()> {
+        self.future.poll()
+    }
+}
+
+impl<F> Drop for Rejection<F> {
+    fn drop(&mut self) {
+        self.inner.rejecting.fetch_sub(1, Ordering::SeqCst);
+        self.inner.task.notify();
+    }
+}
+
+impl Drop for OverloadGuard {
+    fn drop(&mut self) {
+        self.inner.active.fetch_sub(1, Ordering::SeqCst);
+        self.inner.task.notify();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::min;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::{Future, Stream, Async, Poll};
+    use futures::future::{empty, lazy};
+    use futures::sync::{mpsc, oneshot};
+    use tokio::clock;
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    use crate::guarded::Guarded;
+    use crate::test_util::Task;
+    use crate::traits::ListenExt;
+    use super::{Close, OverloadGuard, Reject, Response};
+
+    type Rejection = Box<dyn Future<Item=(), Error=()> + Send>;
+    type Waiting = Vec<(u32, oneshot::Sender<()>)>;
+
+    /// Rejected connections waiting to be finished and number of finished
+    #[derive(Clone, Default)]
+    struct Rejected {
+        waiting: Arc<Mutex<Waiting>>,
+        finished: Arc<AtomicUsize>,
+    }
+
+    impl Rejected {
+        /// Returns a reject policy which finishes rejection on `finish()`
+        fn policy(&self) -> impl FnMut(u32) -> Rejection {
+            let rejected = self.clone();
+            move |id| {
+                let (tx, rx) = oneshot::channel();
+                rejected.waiting.lock().unwrap().push((id, tx));
+                let finished = rejected.finished.clone();
+                Box::new(rx.then(move |_| {
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }))
+            }
+        }
+
+        fn ids(&self) -> Vec<u32> {
+            self.waiting.lock().unwrap().iter().map(|&(id, _)| id).collect()
+        }
+
+        fn finish(&self) {
+            for (_, tx) in self.waiting.lock().unwrap().drain(..) {
+                tx.send(()).unwrap();
+            }
+        }
+
+        fn finished(&self) -> usize {
+            self.finished.load(Ordering::SeqCst)
+        }
+    }
+
+    fn accepted<S>(stream: &mut Task<S>) -> Guarded<u32, OverloadGuard>
+        where S: Stream<Item=Guarded<u32, OverloadGuard>, Error=()>,
+    {
+        match stream.poll_next() {
+            Ok(Async::Ready(Some(sock))) => sock,
+            _ => panic!("socket is not accepted"),
+        }
+    }
+
+    #[test]
+    fn reject_over_limit() {
+        let mut runtime = Runtime::new().unwrap();
+        let rejected = Rejected::default();
+        let (tx, rx) = mpsc::unbounded::<u32>();
+        let mut stream = Task::new(rx.overload(1, rejected.policy()));
+        for id in 1..4 {
+            tx.unbounded_send(id).unwrap();
+        }
+        let first = runtime.block_on(lazy(|| {
+            let first = accepted(&mut stream);
+            assert_eq!(*first.get_ref(), 1);
+            assert!(stream.poll_next().unwrap().is_not_ready());
+            assert_eq!(stream.get_mut().active(), 1);
+            assert_eq!(stream.get_mut().rejecting(), 2);
+            Ok::<_, ()>(first)
+        })).unwrap();
+        assert_eq!(rejected.ids(), vec![2, 3]);
+
+        // rejections are finished without polling the stream
+        rejected.finish();
+        runtime.run().unwrap();
+        assert_eq!(rejected.finished(), 2);
+        assert_eq!(stream.get_mut().rejecting(), 0);
+        assert!(stream.woken());
+        runtime.block_on(lazy(|| {
+            assert!(stream.poll_next().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+
+        // slot is free when socket is dropped
+        drop(first);
+        assert!(stream.woken());
+        assert_eq!(stream.get_mut().active(), 0);
+        runtime.block_on(lazy(|| {
+            tx.unbounded_send(4).unwrap();
+            assert_eq!(*accepted(&mut stream).get_ref(), 4);
+            Ok::<_, ()>(())
+        })).unwrap();
+    }
+
+    #[test]
+    fn max_rejecting() {
+        let mut runtime = Runtime::new().unwrap();
+        let rejected = Rejected::default();
+        let (tx, rx) = mpsc::unbounded::<u32>();
+        let mut stream = rx.overload(0, rejected.policy());
+        stream.max_rejecting(1);
+        let mut stream = Task::new(stream);
+        for id in 1..4 {
+            tx.unbounded_send(id).unwrap();
+        }
+        runtime.block_on(lazy(|| {
+            assert!(stream.poll_next().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+        // the rest are left in the backlog
+        assert_eq!(rejected.ids(), vec![1]);
+
+        rejected.finish();
+        runtime.run().unwrap();
+        assert!(stream.woken());
+        runtime.block_on(lazy(|| {
+            assert!(stream.poll_next().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert_eq!(rejected.ids(), vec![2]);
+    }
+
+    #[test]
+    fn rejections_run_when_listen_is_at_limit() {
+        let mut runtime = Runtime::new().unwrap();
+        let rejected = Rejected::default();
+        let (tx, rx) = mpsc::unbounded::<u32>();
+        let mut listen = Task::new(rx
+            .overload(1, rejected.policy())
+            // connections never finish
+            .map(|sock| empty::<(), ()>().then(move |r| {
+                drop(sock);
+                r
+            }))
+            .listen(2));
+        tx.unbounded_send(1).unwrap();
+        tx.unbounded_send(2).unwrap();
+        runtime.block_on(lazy(|| {
+            assert_eq!(listen.poll(), Ok(Async::NotReady));
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert_eq!(rejected.ids(), vec![2]);
+
+        // listener doesn't poll the stream any more
+        listen.get_mut().limit().set(1);
+        runtime.block_on(lazy(|| {
+            assert_eq!(listen.poll(), Ok(Async::NotReady));
+            Ok::<_, ()>(())
+        })).unwrap();
+        rejected.finish();
+        runtime.run().unwrap();
+        assert_eq!(rejected.finished(), 1);
+    }
+
+    #[test]
+    fn close() {
+        let mut runtime = Runtime::new().unwrap();
+        let (tx, rx) = mpsc::unbounded::<u32>();
+        let mut stream = Task::new(rx.overload(0, Close));
+        tx.unbounded_send(1).unwrap();
+        drop(tx);
+        runtime.block_on(lazy(|| {
+            match stream.poll_next() {
+                Ok(Async::Ready(None)) => {}
+                _ => panic!("stream is not finished"),
+            }
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert_eq!(stream.get_mut().rejecting(), 1);
+        runtime.run().unwrap();
+        assert_eq!(stream.get_mut().rejecting(), 0);
+    }
+
+    /// State of the socket shared with the test
+    #[derive(Default)]
+    struct State {
+        written: Vec<u8>,
+        input: Vec<u8>,
+        shutdown: bool,
+        stalled: bool,
+    }
+
+    /// A socket which accepts up to 10 bytes per write
+    #[derive(Clone, Default)]
+    struct Socket(Arc<Mutex<State>>);
+
+    fn would_block() -> io::Error {
+        io::ErrorKind::WouldBlock.into()
+    }
+
+    impl io::Read for Socket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut state = self.0.lock().unwrap();
+            if state.stalled {
+                return Err(would_block());
+            }
+            let n = min(buf.len(), state.input.len());
+            buf[..n].copy_from_slice(&state.input[..n]);
+            state.input.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl io::Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut state = self.0.lock().unwrap();
+            if state.stalled {
+                return Err(would_block());
+            }
+            let n = min(buf.len(), 10);
+            state.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for Socket {}
+
+    impl AsyncWrite for Socket {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            self.0.lock().unwrap().shutdown = true;
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn http_unavailable() {
+        let response = Response::http_unavailable(Duration::from_millis(1500));
+        let data = String::from_utf8(response.data.to_vec()).unwrap();
+        assert!(data.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(data.contains("\r\nRetry-After: 2\r\n"));
+        assert!(data.contains("\r\nConnection: close\r\n"));
+        assert!(data.ends_with("\r\n\r\nService Unavailable\n"));
+        assert!(data.contains("\r\nContent-Length: 20\r\n"));
+        let response = Response::http_unavailable(Duration::new(5, 0));
+        let data = String::from_utf8(response.data.to_vec()).unwrap();
+        assert!(data.contains("\r\nRetry-After: 5\r\n"));
+    }
+
+    #[test]
+    fn respond() {
+        let socket = Socket::default();
+        socket.0.lock().unwrap().input = b"GET / HTTP/1.1\r\n\r\n".to_vec();
+        let mut response = Response::new(&b"overloaded, try again later"[..]);
+        let respond = response.reject(socket.clone());
+        Runtime::new().unwrap().block_on(respond).unwrap();
+        let state = socket.0.lock().unwrap();
+        assert_eq!(&state.written[..], &b"overloaded, try again later"[..]);
+        assert!(state.shutdown);
+        // request is read before closing the socket
+        assert!(state.input.is_empty());
+    }
+
+    #[test]
+    fn respond_timeout() {
+        let mut runtime = Runtime::new().unwrap();
+        let socket = Socket::default();
+        socket.0.lock().unwrap().stalled = true;
+        let mut response = Response::new("overloaded");
+        response.timeout(Duration::from_millis(10));
+        let mut respond = runtime.block_on(lazy(|| {
+            let mut respond = Task::new(response.reject(socket.clone()));
+            assert_eq!(respond.poll(), Ok(Async::NotReady));
+            Ok::<_, ()>(respond)
+        })).unwrap();
+        let deadline = clock::now() + Duration::from_millis(20);
+        runtime.block_on(Delay::new(deadline)).unwrap();
+        assert!(respond.woken());
+        runtime.block_on(lazy(|| {
+            assert_eq!(respond.poll(), Ok(Async::Ready(())));
+            Ok::<_, ()>(())
+        })).unwrap();
+        assert!(socket.0.lock().unwrap().written.is_empty());
+    }
+}
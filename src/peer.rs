@@ -0,0 +1,15 @@
+use std::io;
+use std::net::SocketAddr;
+
+
+/// A socket which knows the address of its peer
+///
+/// Used by filters and bans. For sockets decoded by `ProxyProtocol` this
+/// is the address of the original client rather than of the proxy.
+///
+/// The same trait is used by `per_ip_limit` and `peer_rate_limit` of the
+/// crate root, where it's also implemented for tokio 0.1 `TcpStream`.
+pub trait PeerAddr {
+    /// Returns address of the remote peer
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
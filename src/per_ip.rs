@@ -0,0 +1,404 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use futures::{Stream, Async};
+use futures::task::{self, Task};
+use tokio::clock;
+use tokio::timer::Delay;
+
+use crate::guarded::Guarded;
+use crate::rate_limit;
+use crate::util::mask;
+use crate::traits::PeerAddr;
+
+
+struct State {
+    active: HashMap<IpAddr, usize>,
+    task: Option<Task>,
+}
+
+/// A guard that holds a per-IP slot, see `ListenExt::per_ip_limit`
+pub struct IpGuard {
+    ip: IpAddr,
+    state: Arc<Mutex<State>>,
+}
+
+/// A structure returned by `ListenExt::per_ip_limit`
+///
+/// This is a stream which tracks number of active connections from each
+/// client IP address and either closes or delays connections over the
+/// limit.
+///
+/// Connection is considered active until the socket (wrapped into a
+/// `Guarded`) is dropped.
+///
+/// IPv6 addresses are aggregated by a prefix, because a single client
+/// usually owns a whole `/64` network (use `ipv6_prefix` to change that).
+/// IPv4-mapped IPv6 addresses (which you get when listening on `[::]`)
+/// are treated as IPv4 ones.
+pub struct PerIpLimit<S: Stream> {
+    stream: S,
+    max_per_ip: usize,
+    ipv6_prefix: u8,
+    max_delayed: usize,
+    delay_timeout: Duration,
+    delayed: VecDeque<(IpAddr, Instant, S::Item)>,
+    timer: Option<Delay>,
+    state: Arc<Mutex<State>>,
+}
+
+pub fn new<S: Stream>(stream: S, max_per_ip: usize) -> PerIpLimit<S> {
+    PerIpLimit {
+        stream,
+        max_per_ip,
+        ipv6_prefix: 64,
+        max_delayed: 0,
+        delay_timeout: Duration::new(10, 0),
+        delayed: VecDeque::new(),
+        timer: None,
+        state: Arc::new(Mutex::new(State {
+            active: HashMap::new(),
+            task: None,
+        })),
+    }
+}
+
+impl State {
+    fn try_acquire(&mut self, ip: IpAddr, max_per_ip: usize) -> bool {
+        let num = self.active.get(&ip).cloned().unwrap_or(0);
+        if num < max_per_ip {
+            self.active.insert(ip, num + 1);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<S: Stream> PerIpLimit<S> {
+    /// Sets length of the IPv6 prefix which is considered a single client
+    ///
+    /// By default it's `64`, use `128` to count each address separately.
+    pub fn ipv6_prefix(&mut self, bits: u8) -> &mut Self {
+        self.ipv6_prefix = bits;
+        self
+    }
+
+    /// Delay connections over the limit instead of closing them
+    ///
+    /// Up to `max_delayed` accepted sockets (in total for all clients) are
+    /// kept until one of the connections from the same client is closed.
+    /// When there are already `max_delayed` connections waiting, new
+    /// connections over the limit are closed. By default (`0`) all
+    /// connections over the limit are closed immediately.
+    ///
+    /// Delayed sockets are closed if they can't be accepted within
+    /// `delay_timeout`. This requires tokio timer, so the stream must be
+    /// polled within tokio runtime when delaying is enabled.
+    pub fn delay(&mut self, max_delayed: usize) -> &mut Self {
+        self.max_delayed = max_delayed;
+        self
+    }
+
+    /// Sets maximum time a connection can be delayed (default 10 seconds)
+    ///
+    /// Applies to connections delayed after the call.
+    pub fn delay_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.delay_timeout = timeout;
+        self
+    }
+
+    /// Returns number of active connections from the client with this
+    /// address
+    pub fn connections(&self, ip: IpAddr) -> usize {
+        let key = mask(ip, self.ipv6_prefix);
+        self.lock().active.get(&key).cloned().unwrap_or(0)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("per-ip state is not poisoned")
+    }
+
+    fn guard(&self, ip: IpAddr) -> IpGuard {
+        IpGuard {
+            ip,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Returns a delayed socket which has a free slot now, closing
+    /// sockets which have been delayed for too long
+    fn poll_delayed(&mut self, now: Instant) -> Option<(IpAddr, S::Item)> {
+        self.delayed.retain(|&(ip, deadline, _)| {
+            if deadline <= now {
+                debug!("Connection from {} delayed for too long, closing",
+                    ip);
+                return false;
+            }
+            true
+        });
+        if self.delayed.is_empty() {
+            self.timer = None;
+            return None;
+        }
+        let mut state = self.lock();
+        let max_per_ip = self.max_per_ip;
+        let ready = self.delayed.iter()
+            .position(|&(ip, _, _)| state.try_acquire(ip, max_per_ip));
+        match ready {
+            Some(idx) => {
+                drop(state);
+                let (ip, _, sock) = self.delayed.remove(idx)
+                    .expect("valid index");
+                Some((ip, sock))
+            }
+            None => {
+                state.task = Some(task::current());
+                None
+            }
+        }
+    }
+
+    /// Sets timer to the nearest deadline of the delayed sockets
+    ///
+    /// Returns `true` if the deadline is already reached.
+    fn wait_delayed(&mut self, now: Instant) -> bool {
+        let deadline = self.delayed.iter()
+            .map(|&(_, deadline, _)| deadline)
+            .min();
+        match deadline {
+            Some(deadline) => {
+                deadline <= now ||
+                    rate_limit::wait(&mut self.timer, now, deadline - now)
+            }
+            None => {
+                self.timer = None;
+                false
+            }
+        }
+    }
+}
+
+impl IpGuard {
+    /// Returns the client address (masked by prefix in case of IPv6)
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+}
+
+impl<S: Stream> Stream for PerIpLimit<S>
+    where S::Item: PeerAddr,
+{
+    type Item = Guarded<S::Item, IpGuard>;
+    type Error = S::Error;
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, S::Error> {
+        loop {
+            let now = clock::now();
+            if let Some((ip, sock)) = self.poll_delayed(now) {
+                return Ok(Async::Ready(Some(
+                    Guarded::new(sock, self.guard(ip)))));
+            }
+            let sock = match self.stream.poll()? {
+                Async::Ready(Some(sock)) => sock,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => {
+                    if self.wait_delayed(now) {
+                        continue;
+                    }
+                    return Ok(Async::NotReady);
+                }
+            };
+            let ip = match sock.peer_addr() {
+                Ok(addr) => mask(addr.ip(), self.ipv6_prefix),
+                Err(e) => {
+                    debug!("Can't get peer address: {}", e);
+                    continue;
+                }
+            };
+            if self.lock().try_acquire(ip, self.max_per_ip) {
+                return Ok(Async::Ready(Some(
+                    Guarded::new(sock, self.guard(ip)))));
+            }
+            if self.delayed.len() < self.max_delayed {
+                debug!("Too many connections from {}, delaying", ip);
+                self.lock().task = Some(task::current());
+                let deadline = now + self.delay_timeout;
+                self.delayed.push_back((ip, deadline, sock));
+            } else {
+                debug!("Too many connections from {}, closing", ip);
+            }
+        }
+    }
+}
+
+impl Drop for IpGuard {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            let empty = match state.active.get_mut(&self.ip) {
+                Some(num) => {
+                    *num -= 1;
+                    *num == 0
+                }
+                None => false,
+            };
+            if empty {
+                state.active.remove(&self.ip);
+            }
+            if let Some(task) = state.task.take() {
+                task.notify();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use futures::Async;
+    use futures::future::lazy;
+    use futures::sync::mpsc::{self, UnboundedReceiver};
+    use tokio::clock;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    use crate::guarded::Guarded;
+    use crate::test_util::{Task, Socket, socket};
+    use crate::traits::ListenExt;
+    use super::{PerIpLimit, IpGuard};
+
+    type Limit = Task<PerIpLimit<UnboundedReceiver<Socket>>>;
+    type Sock = Guarded<Socket, IpGuard>;
+
+    fn next(limit: &mut Limit) -> Option<Sock> {
+        match limit.poll_next().expect("stream never fails") {
+            Async::Ready(Some(sock)) => Some(sock),
+            Async::Ready(None) => panic!("stream is closed"),
+            Async::NotReady => None,
+        }
+    }
+
+    fn port(sock: Option<Sock>) -> u16 {
+        sock.expect("socket accepted").get_ref().0.port()
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().expect("valid ip")
+    }
+
+    #[test]
+    fn accounting() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut limit = Task::new(rx.per_ip_limit(2));
+        tx.unbounded_send(socket("10.0.0.1:1000")).unwrap();
+        tx.unbounded_send(socket("10.0.0.1:1001")).unwrap();
+        tx.unbounded_send(socket("10.0.0.1:1002")).unwrap();
+        tx.unbounded_send(socket("10.0.0.2:1000")).unwrap();
+        let first = next(&mut limit).unwrap();
+        let second = next(&mut limit).unwrap();
+        // third one is closed
+        let other = next(&mut limit).unwrap();
+        assert_eq!(other.guard().ip(), ip("10.0.0.2"));
+        assert!(next(&mut limit).is_none());
+        assert_eq!(limit.get_mut().connections(ip("10.0.0.1")), 2);
+        assert_eq!(limit.get_mut().connections(ip("10.0.0.2")), 1);
+
+        drop(first);
+        assert_eq!(limit.get_mut().connections(ip("10.0.0.1")), 1);
+        tx.unbounded_send(socket("10.0.0.1:1003")).unwrap();
+        assert_eq!(port(next(&mut limit)), 1003);
+        drop((second, other));
+        assert_eq!(limit.get_mut().connections(ip("10.0.0.1")), 0);
+        assert_eq!(limit.get_mut().connections(ip("10.0.0.2")), 0);
+    }
+
+    #[test]
+    fn ipv6_prefix() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut limit = Task::new(rx.per_ip_limit(1));
+        tx.unbounded_send(socket("[2001:db8::1]:1000")).unwrap();
+        // same /64 network, closed
+        tx.unbounded_send(socket("[2001:db8::2]:1001")).unwrap();
+        tx.unbounded_send(socket("[2001:db8:0:1::1]:1002")).unwrap();
+        tx.unbounded_send(socket("10.0.0.1:1003")).unwrap();
+        // mapped IPv4 address is the same client as the IPv4 one
+        tx.unbounded_send(socket("[::ffff:10.0.0.1]:1004")).unwrap();
+        let a = next(&mut limit).unwrap();
+        assert_eq!(a.guard().ip(), ip("2001:db8::"));
+        assert_eq!(port(next(&mut limit)), 1002);
+        let b = next(&mut limit).unwrap();
+        assert_eq!(b.guard().ip(), ip("10.0.0.1"));
+        assert!(next(&mut limit).is_none());
+        assert_eq!(limit.get_mut().connections(ip("2001:db8::ff")), 1);
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut limit = rx.per_ip_limit(1);
+        limit.ipv6_prefix(128);
+        let mut limit = Task::new(limit);
+        tx.unbounded_send(socket("[2001:db8::1]:1000")).unwrap();
+        tx.unbounded_send(socket("[2001:db8::2]:1001")).unwrap();
+        let _a = next(&mut limit).unwrap();
+        let _b = next(&mut limit).unwrap();
+        assert_eq!(limit.get_mut().connections(ip("2001:db8::2")), 1);
+        assert_eq!(limit.get_mut().connections(ip("2001:db8::3")), 0);
+    }
+
+    #[test]
+    fn delayed_release() {
+        Runtime::new().unwrap().block_on(lazy(|| {
+            let (tx, rx) = mpsc::unbounded();
+            let mut limit = rx.per_ip_limit(1);
+            limit.delay(1);
+            let mut limit = Task::new(limit);
+            tx.unbounded_send(socket("10.0.0.1:1000")).unwrap();
+            tx.unbounded_send(socket("10.0.0.1:1001")).unwrap();
+            tx.unbounded_send(socket("10.0.0.1:1002")).unwrap();
+            tx.unbounded_send(socket("10.0.0.2:1003")).unwrap();
+            let first = next(&mut limit);
+            // second is delayed, third is closed as the queue is full
+            let other = next(&mut limit).unwrap();
+            assert_eq!(other.guard().ip(), ip("10.0.0.2"));
+            assert!(next(&mut limit).is_none());
+            assert!(!limit.woken());
+
+            drop(first);
+            assert!(limit.woken());
+            let second = next(&mut limit).unwrap();
+            assert_eq!(second.get_ref().0.port(), 1001);
+            assert_eq!(limit.get_mut().connections(ip("10.0.0.1")), 1);
+            assert_eq!(limit.get_mut().connections(ip("10.0.0.2")), 1);
+            Ok::<(), ()>(())
+        })).unwrap();
+    }
+
+    #[test]
+    fn delay_timeout() {
+        let mut runtime = Runtime::new().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let mut limit = rx.per_ip_limit(1);
+        limit.delay(10).delay_timeout(Duration::from_millis(10));
+        let mut limit = Task::new(limit);
+        tx.unbounded_send(socket("10.0.0.1:1000")).unwrap();
+        tx.unbounded_send(socket("10.0.0.1:1001")).unwrap();
+        let first = runtime.block_on(lazy(|| {
+            let first = next(&mut limit);
+            assert!(next(&mut limit).is_none());
+            Ok::<_, ()>(first)
+        })).unwrap();
+        let deadline = clock::now() + Duration::from_millis(20);
+        runtime.block_on(Delay::new(deadline)).unwrap();
+        runtime.block_on(lazy(|| {
+            // timer has woken up the stream, so delayed socket is closed
+            assert!(limit.woken());
+            assert!(next(&mut limit).is_none());
+            drop(first);
+            assert!(next(&mut limit).is_none());
+            tx.unbounded_send(socket("10.0.0.1:1002")).unwrap();
+            assert_eq!(port(next(&mut limit)), 1002);
+            Ok::<(), ()>(())
+        })).unwrap();
+    }
+}
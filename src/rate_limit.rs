@@ -0,0 +1,361 @@
+use std::cmp::max;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream, Async};
+use tokio::clock;
+use tokio::timer::Delay;
+
+use crate::util::mask;
+use crate::traits::PeerAddr;
+
+
+/// A token bucket
+///
+/// Bucket starts full. Each accepted connection takes a token, tokens are
+/// refilled at `per_second` rate up to `burst`.
+#[derive(Debug, Clone)]
+pub struct Bucket {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub fn new(per_second: u32, burst: u32) -> Bucket {
+        Bucket::new_at(per_second, burst, clock::now())
+    }
+
+    fn new_at(per_second: u32, burst: u32, now: Instant) -> Bucket {
+        let burst = f64::from(max(burst, 1));
+        Bucket {
+            per_second: f64::from(per_second),
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.updated {
+            let secs = (now - self.updated).as_secs_f64();
+            self.tokens = (self.tokens + secs * self.per_second)
+                .min(self.burst);
+            self.updated = now;
+        }
+    }
+
+    /// Returns `None` if there is a token available, or time to wait
+    /// until the next token otherwise
+    pub fn check(&mut self, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            None
+        } else if self.per_second <= 0.0 {
+            // never refilled, but we still need to wake up at some point
+            Some(Duration::new(1, 0))
+        } else {
+            let secs = (1.0 - self.tokens) / self.per_second;
+            Some(Duration::from_secs_f64(secs))
+        }
+    }
+
+    /// Takes a token, `check()` must be called first
+    pub fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.burst
+    }
+}
+
+/// Polls the timer for the specified duration
+///
+/// Timer is reused (reset to the new deadline) if it's already created.
+/// Returns `true` if timer is already expired, so caller should retry.
+pub fn wait(timer: &mut Option<Delay>, now: Instant, delay: Duration)
+    -> bool
+{
+    let deadline = now + delay;
+    let timer = match *timer {
+        Some(ref mut timer) => {
+            if timer.deadline() != deadline {
+                timer.reset(deadline);
+            }
+            timer
+        }
+        None => timer.get_or_insert_with(|| Delay::new(deadline)),
+    };
+    match timer.poll().expect("delay never fails") {
+        Async::Ready(()) => true,
+        Async::NotReady => false,
+    }
+}
+
+/// A structure returned by `ListenExt::rate_limit`
+///
+/// This is a stream that limits the rate of accepted connections using
+/// a token bucket. When bucket is empty, the underlying stream isn't
+/// polled, so clients wait in the kernel backlog, instead of being
+/// accepted and dropped.
+pub struct RateLimit<S> {
+    stream: S,
+    bucket: Bucket,
+    timer: Option<Delay>,
+}
+
+/// A structure returned by `ListenExt::peer_rate_limit`
+///
+/// This is a stream that limits the rate of connections from every single
+/// client IP address using a token bucket per address. Since address is
+/// only known after connection is accepted, connections over the limit are
+/// closed immediately.
+///
+/// IPv6 addresses are aggregated by `/64` prefix, like in `PerIpLimit`.
+pub struct PeerRateLimit<S> {
+    stream: S,
+    per_second: u32,
+    burst: u32,
+    ipv6_prefix: u8,
+    buckets: HashMap<IpAddr, Bucket>,
+    cleanup_at: usize,
+}
+
+pub fn new<S>(stream: S, per_second: u32, burst: u32) -> RateLimit<S> {
+    RateLimit {
+        stream,
+        bucket: Bucket::new(per_second, burst),
+        timer: None,
+    }
+}
+
+pub fn new_per_peer<S>(stream: S, per_second: u32, burst: u32)
+    -> PeerRateLimit<S>
+{
+    PeerRateLimit {
+        stream,
+        per_second,
+        burst,
+        ipv6_prefix: 64,
+        buckets: HashMap::new(),
+        cleanup_at: 1024,
+    }
+}
+
+impl<S: Stream> Stream for RateLimit<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> Result<Async<Option<S::Item>>, S::Error> {
+        if let Some(ref mut timer) = self.timer {
+            if let Async::NotReady = timer.poll().expect("delay never fails")
+            {
+                return Ok(Async::NotReady);
+            }
+        }
+        loop {
+            let now = clock::now();
+            if let Some(delay) = self.bucket.check(now) {
+                if wait(&mut self.timer, now, delay) {
+                    continue;
+                }
+                return Ok(Async::NotReady);
+            }
+            let result = self.stream.poll()?;
+            if let Async::Ready(Some(_)) = result {
+                self.bucket.take();
+            }
+            return Ok(result);
+        }
+    }
+}
+
+impl<S> PeerRateLimit<S> {
+    /// Sets length of the IPv6 prefix which is considered a single client
+    ///
+    /// By default it's `64`, use `128` to count each address separately.
+    pub fn ipv6_prefix(&mut self, bits: u8) -> &mut Self {
+        self.ipv6_prefix = bits;
+        self
+    }
+
+    /// Removes buckets that are full, they are equal to absent ones
+    fn cleanup(&mut self, now: Instant) {
+        self.buckets.retain(|_, b| !b.is_full(now));
+        self.cleanup_at = max(1024, self.buckets.len()*2);
+    }
+
+    /// Takes a token from the bucket of the client, returns `false` if
+    /// there are no tokens left
+    fn acquire(&mut self, ip: IpAddr, now: Instant) -> bool {
+        if self.buckets.len() >= self.cleanup_at {
+            self.cleanup(now);
+        }
+        let (per_second, burst) = (self.per_second, self.burst);
+        let bucket = self.buckets.entry(ip)
+            .or_insert_with(|| Bucket::new_at(per_second, burst, now));
+        if bucket.check(now).is_none() {
+            bucket.take();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<S: Stream> Stream for PeerRateLimit<S>
+    where S::Item: PeerAddr,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> Result<Async<Option<S::Item>>, S::Error> {
+        loop {
+            let sock = match self.stream.poll()? {
+                Async::Ready(Some(sock)) => sock,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            let ip = match sock.peer_addr() {
+                Ok(addr) => mask(addr.ip(), self.ipv6_prefix),
+                Err(e) => {
+                    debug!("Can't get peer address: {}", e);
+                    continue;
+                }
+            };
+            if self.acquire(ip, clock::now()) {
+                return Ok(Async::Ready(Some(sock)));
+            }
+            debug!("Connection rate from {} exceeded, closing", ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    use futures::Async;
+    use futures::future::lazy;
+    use futures::sync::mpsc;
+    use tokio::clock;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    use crate::test_util::{Task, Socket, socket};
+    use crate::traits::ListenExt;
+    use super::Bucket;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().expect("valid ip")
+    }
+
+    #[test]
+    fn bucket_refill() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new_at(10, 2, start);
+        assert_eq!(bucket.check(start), None);
+        bucket.take();
+        assert_eq!(bucket.check(start), None);
+        bucket.take();
+        let wait = bucket.check(start).unwrap();
+        assert!(wait > ms(99) && wait <= ms(100));
+        let wait = bucket.check(start + ms(50)).unwrap();
+        assert!(wait > ms(49) && wait <= ms(50));
+        assert_eq!(bucket.check(start + ms(100)), None);
+        bucket.take();
+        assert!(bucket.check(start + ms(100)).is_some());
+
+        // refilled up to burst only
+        assert!(bucket.is_full(start + ms(10_000)));
+        bucket.take();
+        bucket.take();
+        assert!(bucket.check(start + ms(10_000)).is_some());
+
+        // time going backwards doesn't add tokens
+        assert!(bucket.check(start).is_some());
+    }
+
+    #[test]
+    fn bucket_never_refilled() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new_at(0, 0, start);
+        assert_eq!(bucket.check(start), None);
+        bucket.take();
+        assert_eq!(bucket.check(start + ms(10_000)), Some(ms(1000)));
+    }
+
+    #[test]
+    fn peer_tokens() {
+        let start = Instant::now();
+        let (_tx, rx) = mpsc::unbounded::<Socket>();
+        let mut limit = rx.peer_rate_limit(1, 2);
+        assert!(limit.acquire(ip("10.0.0.1"), start));
+        assert!(limit.acquire(ip("10.0.0.1"), start));
+        assert!(!limit.acquire(ip("10.0.0.1"), start));
+        // other clients have their own buckets
+        assert!(limit.acquire(ip("10.0.0.2"), start));
+        assert!(!limit.acquire(ip("10.0.0.1"), start + ms(900)));
+        assert!(limit.acquire(ip("10.0.0.1"), start + ms(1000)));
+        assert!(!limit.acquire(ip("10.0.0.1"), start + ms(1000)));
+
+        // full buckets are removed
+        assert_eq!(limit.buckets.len(), 2);
+        limit.cleanup(start + ms(1500));
+        assert_eq!(limit.buckets.len(), 1);
+        limit.cleanup(start + ms(3000));
+        assert_eq!(limit.buckets.len(), 0);
+    }
+
+    #[test]
+    fn peer_rate_limit() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut limit = Task::new(rx.peer_rate_limit(0, 1));
+        tx.unbounded_send(socket("10.0.0.1:1000")).unwrap();
+        tx.unbounded_send(socket("10.0.0.1:1001")).unwrap();
+        tx.unbounded_send(socket("[2001:db8::1]:1002")).unwrap();
+        // same /64 network
+        tx.unbounded_send(socket("[2001:db8::2]:1003")).unwrap();
+        tx.unbounded_send(socket("10.0.0.2:1004")).unwrap();
+        let mut ports = Vec::new();
+        while let Ok(Async::Ready(Some(sock))) = limit.poll_next() {
+            ports.push(sock.0.port());
+        }
+        assert_eq!(ports, vec![1000, 1002, 1004]);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut runtime = Runtime::new().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let mut limit = Task::new(rx.rate_limit(100, 2));
+        for i in 0..4 {
+            tx.unbounded_send(i).unwrap();
+        }
+        runtime.block_on(lazy(|| {
+            assert_eq!(limit.poll_next(), Ok(Async::Ready(Some(0))));
+            assert_eq!(limit.poll_next(), Ok(Async::Ready(Some(1))));
+            // clients are left in the backlog while bucket is empty
+            assert_eq!(limit.poll_next(), Ok(Async::NotReady));
+            assert!(limit.get_mut().timer.is_some());
+            assert_eq!(limit.poll_next(), Ok(Async::NotReady));
+            Ok::<(), ()>(())
+        })).unwrap();
+        runtime.block_on(Delay::new(clock::now() + ms(30))).unwrap();
+        runtime.block_on(lazy(|| {
+            assert!(limit.woken());
+            // bucket is refilled up to burst
+            assert_eq!(limit.poll_next(), Ok(Async::Ready(Some(2))));
+            assert_eq!(limit.poll_next(), Ok(Async::Ready(Some(3))));
+            assert_eq!(limit.poll_next(), Ok(Async::NotReady));
+            Ok::<(), ()>(())
+        })).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::task::AtomicTask;
+use tokio::clock;
+
+
+struct Inner {
+    after: Duration,
+    since: Mutex<Option<Instant>>,
+    task: AtomicTask,
+}
+
+/// Tells `BindMany` to close listening sockets while `Listen` is at limit
+///
+/// Keeping listening sockets open while the limit is reached makes
+/// L4 load balancers send new connections to a saturated node, where they
+/// wait in the kernel backlog. With this handle, once the limit has been
+/// reached for `after` time, sockets are closed (so connections are
+/// refused and balancer fails over to other nodes) and they are bound
+/// again as soon as there is free capacity:
+///
+/// ```rust,ignore
+/// let saturation = Saturation::new(Duration::from_secs(5));
+/// let mut bind = BindMany::new(address_stream);
+/// bind.close_when_saturated(&saturation);
+/// let mut listen = bind
+///     .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+///     .map(|socket| Proto::new(socket))
+///     .listen(MAX_SIMULTANEOUS_CONNECTIONS);
+/// listen.saturation(&saturation);
+/// ```
+///
+/// Note: connections that are in the backlog when socket is closed are
+/// reset. All sockets of the `BindMany` are closed, so bind admin or
+/// health check addresses with a separate `BindMany` and `Listen`.
+#[derive(Clone)]
+pub struct Saturation {
+    inner: Arc<Inner>,
+}
+
+impl Saturation {
+    /// Create a handle which considers listener saturated when it's at
+    /// the limit for `after` time
+    pub fn new(after: Duration) -> Saturation {
+        Saturation {
+            inner: Arc::new(Inner {
+                after,
+                since: Mutex::new(None),
+                task: AtomicTask::new(),
+            }),
+        }
+    }
+
+    /// Returns `true` if listener is at the limit for long enough
+    pub fn is_saturated(&self) -> bool {
+        match self.deadline() {
+            Some(deadline) => clock::now() >= deadline,
+            None => false,
+        }
+    }
+
+    /// Returns the time when listener becomes saturated if it's at limit
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        let since = self.inner.since.lock()
+            .expect("saturation is not poisoned");
+        since.map(|since| since + self.inner.after)
+    }
+
+    /// Registers current task to be woken up when limit is left
+    pub(crate) fn register(&self) {
+        self.inner.task.register();
+    }
+
+    pub(crate) fn set_at_limit(&self, value: bool) {
+        let mut since = self.inner.since.lock()
+            .expect("saturation is not poisoned");
+        match (value, *since) {
+            (true, None) => *since = Some(clock::now()),
+            (false, Some(_)) => {
+                *since = None;
+                self.inner.task.notify();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::Async;
+    use futures::sync::mpsc;
+
+    use crate::test_util::{Task, Conn, conn};
+    use crate::traits::ListenExt;
+    use super::Saturation;
+
+    #[test]
+    fn listen_at_limit() {
+        let saturation = Saturation::new(Duration::new(0, 0));
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = rx.listen(1);
+        listen.saturation(&saturation);
+        let stats = listen.stats();
+        let mut listen = Task::new(listen);
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert!(!saturation.is_saturated());
+
+        let (first, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        let (_second, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert!(saturation.is_saturated());
+        // stream is polled once, so the next connection is held
+        assert_eq!(stats.accepted(), 1);
+
+        // held connection is accepted when there is a free slot
+        first.send(()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.accepted(), 2);
+        assert_eq!(stats.active(), 1);
+        assert!(saturation.is_saturated());
+
+        drop(listen);
+        assert!(!saturation.is_saturated());
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures::task::{self, Task};
+
+
+struct Pipeline {
+    reserved: usize,
+    used: usize,
+    /// Task waiting for a slot and the order it started waiting in
+    task: Option<(usize, Task)>,
+    /// Woken up to take a slot, but haven't tried yet
+    woken: bool,
+}
+
+struct State {
+    capacity: usize,
+    used: usize,
+    next_id: usize,
+    next_waiter: usize,
+    pipelines: HashMap<usize, Pipeline>,
+}
+
+/// A connection limit shared by multiple `Listen` futures
+///
+/// This works like a semaphore: each connection of every listener attached
+/// with `Listen::shared_limit` holds one slot until it's finished. When
+/// no slots are left, listeners stop accepting connections (until some
+/// connection in any of the listeners is closed).
+///
+/// Every listener can reserve a minimum number of slots which other
+/// listeners can't use. This is useful, for example, to make admin
+/// interface accessible even if public one has exhausted the limit:
+///
+/// ```rust,ignore
+/// let limit = SharedLimit::new(10000);
+/// let mut public = public_stream.listen(10000);
+/// public.shared_limit(&limit, 0);
+/// let mut admin = admin_stream.listen(100);
+/// admin.shared_limit(&limit, 10);
+/// ```
+///
+/// Note: listener's own `max_connections` is still in effect.
+#[derive(Clone)]
+pub struct SharedLimit {
+    state: Arc<Mutex<State>>,
+}
+
+/// A part of `SharedLimit` used by a single listener
+pub struct Share {
+    id: usize,
+    state: Arc<Mutex<State>>,
+}
+
+impl State {
+    /// Number of slots that can be used by any pipeline
+    fn unreserved(&self) -> usize {
+        let reserved = self.pipelines.values()
+            .map(|p| p.reserved.saturating_sub(p.used))
+            .sum();
+        self.capacity.saturating_sub(self.used).saturating_sub(reserved)
+    }
+    /// Wakes up to `num` pipelines which can take a slot, in the order
+    /// they started waiting
+    fn notify_waiting(&mut self, num: usize) {
+        let free = self.capacity.saturating_sub(self.used);
+        let unreserved = self.unreserved();
+        let mut waiting = self.pipelines.iter()
+            .filter(|(_, p)| unreserved > 0 || p.used < p.reserved)
+            .filter_map(|(&id, p)| p.task.as_ref().map(|&(seq, _)| (seq, id)))
+            .collect::<Vec<_>>();
+        waiting.sort_unstable();
+        for (_, id) in waiting.into_iter().take(num.min(free)) {
+            let p = self.pipelines.get_mut(&id).expect("pipeline exists");
+            if let Some((_, task)) = p.task.take() {
+                p.woken = true;
+                task.notify();
+            }
+        }
+    }
+}
+
+impl SharedLimit {
+    /// Create a new limit with the specified number of slots
+    pub fn new(capacity: usize) -> SharedLimit {
+        SharedLimit {
+            state: Arc::new(Mutex::new(State {
+                capacity,
+                used: 0,
+                next_id: 0,
+                next_waiter: 0,
+                pipelines: HashMap::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("shared limit is not poisoned")
+    }
+
+    pub(crate) fn share(&self, reserved: usize) -> Share {
+        let mut state = self.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.pipelines.insert(id, Pipeline {
+            reserved,
+            used: 0,
+            task: None,
+            woken: false,
+        });
+        Share { id, state: self.state.clone() }
+    }
+
+    /// Returns total number of slots
+    pub fn capacity(&self) -> usize {
+        self.lock().capacity
+    }
+
+    /// Changes total number of slots
+    ///
+    /// Like with `Limit::set`, lowering capacity doesn't close any
+    /// connections, listeners just stop accepting new ones.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.lock();
+        info!("Shared connection limit changed from {} to {}",
+            state.capacity, capacity);
+        state.capacity = capacity;
+        let free = state.capacity.saturating_sub(state.used);
+        state.notify_waiting(free);
+    }
+
+    /// Returns number of slots used by connections of all the listeners
+    pub fn used(&self) -> usize {
+        self.lock().used
+    }
+}
+
+impl Share {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("shared limit is not poisoned")
+    }
+
+    /// Checks whether a slot is available without taking it, or registers
+    /// current task for notification when no slots are available
+    ///
+    /// If the listener was woken up for a free slot, the wakeup is passed
+    /// to the next waiting listener, as this one might not need the slot.
+    pub fn available(&self) -> bool {
+        self.check(false)
+    }
+
+    /// Takes a slot, or registers current task for notification when
+    /// no slots are available
+    pub fn acquire(&self) -> bool {
+        self.check(true)
+    }
+
+    fn check(&self, take: bool) -> bool {
+        let mut state = self.lock();
+        let available = {
+            let p = &state.pipelines[&self.id];
+            state.used < state.capacity &&
+                (p.used < p.reserved || state.unreserved() > 0)
+        };
+        if available && take {
+            state.used += 1;
+        }
+        let seq = state.next_waiter;
+        let p = state.pipelines.get_mut(&self.id)
+            .expect("pipeline is registered");
+        let woken = std::mem::replace(&mut p.woken, false);
+        if available {
+            p.task = None;
+            if take {
+                p.used += 1;
+            } else if woken {
+                state.notify_waiting(1);
+            }
+        } else if p.task.is_none() {
+            p.task = Some((seq, task::current()));
+            state.next_waiter += 1;
+        } else if let Some((_, ref mut task)) = p.task {
+            // keep the place in the queue
+            *task = task::current();
+        }
+        available
+    }
+
+    /// Passes the wakeup to another listener if this one was woken up
+    /// for a free slot but doesn't need it any more
+    pub fn pass(&self) {
+        let mut state = self.lock();
+        let woken = state.pipelines.get_mut(&self.id)
+            .map(|p| std::mem::replace(&mut p.woken, false))
+            .unwrap_or(false);
+        if woken {
+            state.notify_waiting(1);
+        }
+    }
+
+    /// Takes slots without checking the limit
+    pub fn occupy(&self, num: usize) {
+        let mut state = self.lock();
+        state.used += num;
+        if let Some(p) = state.pipelines.get_mut(&self.id) {
+            p.used += num;
+        }
+    }
+
+    /// Returns slots back
+    pub fn release(&self, num: usize) {
+        if num == 0 {
+            return;
+        }
+        let mut state = self.lock();
+        state.used -= num;
+        if let Some(p) = state.pipelines.get_mut(&self.id) {
+            p.used -= num;
+        }
+        state.notify_waiting(num);
+    }
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(p) = state.pipelines.remove(&self.id) {
+                state.used -= p.used;
+                let num = p.used + if p.woken { 1 } else { 0 };
+                state.notify_waiting(num);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Async;
+    use futures::sync::mpsc;
+
+    use crate::test_util::{Task, Conn, conn};
+    use crate::traits::ListenExt;
+    use super::SharedLimit;
+
+    #[test]
+    fn two_listeners() {
+        let limit = SharedLimit::new(2);
+        let (tx1, rx1) = mpsc::unbounded::<Conn>();
+        let (tx2, rx2) = mpsc::unbounded::<Conn>();
+        let mut first = rx1.listen(10);
+        first.shared_limit(&limit, 0);
+        let mut second = rx2.listen(10);
+        second.shared_limit(&limit, 0);
+        let (mut first, mut second) = (Task::new(first), Task::new(second));
+
+        let (a, c) = conn();
+        tx1.unbounded_send(c).unwrap();
+        let (_b, c) = conn();
+        tx1.unbounded_send(c).unwrap();
+        let (_c, c) = conn();
+        tx2.unbounded_send(c).unwrap();
+        assert_eq!(first.poll(), Ok(Async::NotReady));
+        assert_eq!(second.poll(), Ok(Async::NotReady));
+        assert_eq!(limit.used(), 2);
+        assert_eq!(first.get_mut().stats().active(), 2);
+        assert_eq!(second.get_mut().stats().active(), 0);
+
+        // waiter is woken when a slot is freed by another listener
+        a.send(()).unwrap();
+        assert_eq!(first.poll(), Ok(Async::NotReady));
+        assert!(second.woken());
+        assert_eq!(second.poll(), Ok(Async::NotReady));
+        assert_eq!(limit.used(), 2);
+        assert_eq!(first.get_mut().stats().active(), 1);
+        assert_eq!(second.get_mut().stats().active(), 1);
+    }
+
+    #[test]
+    fn idle_listeners_do_not_wake_each_other() {
+        let limit = SharedLimit::new(1);
+        let (_tx1, rx1) = mpsc::unbounded::<Conn>();
+        let (_tx2, rx2) = mpsc::unbounded::<Conn>();
+        let mut first = rx1.listen(10);
+        first.shared_limit(&limit, 0);
+        let mut second = rx2.listen(10);
+        second.shared_limit(&limit, 0);
+        let (mut first, mut second) = (Task::new(first), Task::new(second));
+        for _ in 0..3 {
+            assert_eq!(first.poll(), Ok(Async::NotReady));
+            assert_eq!(second.poll(), Ok(Async::NotReady));
+        }
+        assert!(!first.woken());
+        assert!(!second.woken());
+        assert_eq!(limit.used(), 0);
+    }
+
+    #[test]
+    fn pass() {
+        let limit = SharedLimit::new(2);
+        let (tx1, rx1) = mpsc::unbounded::<Conn>();
+        let (tx2, rx2) = mpsc::unbounded::<Conn>();
+        let (tx3, rx3) = mpsc::unbounded::<Conn>();
+        let mut first = rx1.listen(10);
+        first.shared_limit(&limit, 0);
+        let mut second = rx2.listen(2);
+        second.shared_limit(&limit, 0);
+        let mut third = rx3.listen(10);
+        third.shared_limit(&limit, 0);
+        let mut first = Task::new(first);
+        let mut second = Task::new(second);
+        let mut third = Task::new(third);
+
+        let (a, c) = conn();
+        tx1.unbounded_send(c).unwrap();
+        assert_eq!(first.poll(), Ok(Async::NotReady));
+        let (_b, c) = conn();
+        tx2.unbounded_send(c).unwrap();
+        assert_eq!(second.poll(), Ok(Async::NotReady));
+        assert_eq!(limit.used(), 2);
+        // second and third wait for a slot, in this order
+        assert_eq!(second.poll(), Ok(Async::NotReady));
+        let (_c, c) = conn();
+        tx3.unbounded_send(c).unwrap();
+        assert_eq!(third.poll(), Ok(Async::NotReady));
+
+        // second listener reaches its own limit, so when it's woken up
+        // for a free slot it passes the wakeup to the third one
+        second.get_mut().limit().set(1);
+        assert!(second.woken());
+        a.send(()).unwrap();
+        assert_eq!(first.poll(), Ok(Async::NotReady));
+        assert!(second.woken());
+        assert!(!third.woken());
+        assert_eq!(second.poll(), Ok(Async::NotReady));
+        assert!(third.woken());
+        assert_eq!(third.poll(), Ok(Async::NotReady));
+        assert_eq!(third.get_mut().stats().active(), 1);
+        assert_eq!(limit.used(), 2);
+    }
+
+    #[test]
+    fn pass_when_idle() {
+        let limit = SharedLimit::new(1);
+        let (tx1, rx1) = mpsc::unbounded::<Conn>();
+        let (_tx2, rx2) = mpsc::unbounded::<Conn>();
+        let (tx3, rx3) = mpsc::unbounded::<Conn>();
+        let mut first = rx1.listen(10);
+        first.shared_limit(&limit, 0);
+        let mut second = rx2.listen(10);
+        second.shared_limit(&limit, 0);
+        let mut third = rx3.listen(10);
+        third.shared_limit(&limit, 0);
+        let mut first = Task::new(first);
+        let mut second = Task::new(second);
+        let mut third = Task::new(third);
+
+        let (a, c) = conn();
+        tx1.unbounded_send(c).unwrap();
+        assert_eq!(first.poll(), Ok(Async::NotReady));
+        assert_eq!(second.poll(), Ok(Async::NotReady));
+        let (_c, c) = conn();
+        tx3.unbounded_send(c).unwrap();
+        assert_eq!(third.poll(), Ok(Async::NotReady));
+
+        // second listener has no connections to accept, so the slot goes
+        // to the third one
+        a.send(()).unwrap();
+        assert_eq!(first.poll(), Ok(Async::NotReady));
+        assert!(second.woken());
+        assert!(!third.woken());
+        assert_eq!(second.poll(), Ok(Async::NotReady));
+        assert!(third.woken());
+        assert_eq!(third.poll(), Ok(Async::NotReady));
+        assert_eq!(third.get_mut().stats().active(), 1);
+        assert_eq!(limit.used(), 1);
+        assert!(!second.woken());
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures::{Future, Stream, Async};
+use futures::task::{self, Task};
+
+
+struct State {
+    shutting_down: bool,
+    next_id: usize,
+    connections: usize,
+    acknowledged: usize,
+    tasks: HashMap<usize, Task>,
+}
+
+/// A handle that initiates graceful shutdown of a listener
+///
+/// Create one before building a listening pipeline, use
+/// `ListenExt::with_shutdown` to attach a `Token` to every accepted
+/// connection and call `Shutdown::shutdown` when it's time to stop.
+///
+/// On shutdown the stream returned by `with_shutdown` reaches end-of-stream
+/// (so no more connections are accepted and listening socket is closed)
+/// and every connection token is notified. The `Listen` future resolves as
+/// soon as all connection futures have finished.
+///
+/// The handle is cheap to clone, all clones refer to the same state.
+#[derive(Clone)]
+pub struct Shutdown {
+    state: Arc<Mutex<State>>,
+}
+
+/// A shutdown notification for a single connection
+///
+/// Token is a future which resolves when shutdown is requested. Protocol
+/// handler is expected to finish the request that is currently in
+/// progress and close the connection. Call `acknowledge()` when the
+/// handler has noticed the shutdown so it's reflected in
+/// `Shutdown::acknowledged`.
+pub struct Token {
+    id: usize,
+    acknowledged: bool,
+    state: Arc<Mutex<State>>,
+}
+
+/// A structure returned by `ListenExt::with_shutdown`
+///
+/// This is a stream which yields a pair of original item and a `Token`
+/// and stops when shutdown is requested.
+pub struct WithShutdown<S> {
+    id: usize,
+    stream: Option<S>,
+    shutdown: Shutdown,
+}
+
+pub fn new<S>(stream: S, shutdown: &Shutdown) -> WithShutdown<S> {
+    WithShutdown {
+        id: shutdown.lock().next_id(),
+        stream: Some(stream),
+        shutdown: shutdown.clone(),
+    }
+}
+
+impl State {
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+    /// Returns `true` if shutdown is already in progress, otherwise
+    /// stores current task to be notified later
+    fn register(&mut self, id: usize) -> bool {
+        if self.shutting_down {
+            return true;
+        }
+        self.tasks.insert(id, task::current());
+        false
+    }
+}
+
+impl Shutdown {
+    /// Create a new shutdown handle
+    pub fn new() -> Shutdown {
+        Shutdown {
+            state: Arc::new(Mutex::new(State {
+                shutting_down: false,
+                next_id: 0,
+                connections: 0,
+                acknowledged: 0,
+                tasks: HashMap::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("shutdown state is not poisoned")
+    }
+
+    /// Create a token for a connection
+    ///
+    /// Usually tokens are created by `ListenExt::with_shutdown`, but this
+    /// method is useful if you want to notify some other future too.
+    pub fn token(&self) -> Token {
+        let mut state = self.lock();
+        state.connections += 1;
+        Token {
+            id: state.next_id(),
+            acknowledged: false,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Initiate the shutdown
+    ///
+    /// Subsequent calls do nothing.
+    pub fn shutdown(&self) {
+        let mut state = self.lock();
+        if state.shutting_down {
+            return;
+        }
+        info!("Shutting down, notifying {} connections", state.connections);
+        state.shutting_down = true;
+        for (_, task) in state.tasks.drain() {
+            task.notify();
+        }
+    }
+
+    /// Returns `true` if shutdown has been requested
+    pub fn is_shutting_down(&self) -> bool {
+        self.lock().shutting_down
+    }
+
+    /// Number of tokens (i.e. connections) that are still alive
+    pub fn connections(&self) -> usize {
+        self.lock().connections
+    }
+
+    /// Number of alive connections that have acknowledged shutdown
+    pub fn acknowledged(&self) -> usize {
+        self.lock().acknowledged
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown::new()
+    }
+}
+
+impl Token {
+    /// Returns `true` if shutdown has been requested
+    ///
+    /// Unlike polling the token, this method doesn't register current task
+    /// for notification, so it's useful to check the state between requests.
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.lock().expect("shutdown state is not poisoned")
+            .shutting_down
+    }
+
+    /// Mark this connection as one that has noticed the shutdown
+    ///
+    /// Calling it multiple times is fine, connection is counted once.
+    pub fn acknowledge(&mut self) {
+        if !self.acknowledged {
+            self.acknowledged = true;
+            self.state.lock().expect("shutdown state is not poisoned")
+                .acknowledged += 1;
+        }
+    }
+}
+
+impl Future for Token {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Result<Async<()>, ()> {
+        let mut state = self.state.lock()
+            .expect("shutdown state is not poisoned");
+        if state.register(self.id) {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.connections -= 1;
+            if self.acknowledged {
+                state.acknowledged -= 1;
+            }
+            state.tasks.remove(&self.id);
+        }
+    }
+}
+
+impl<S: Stream> Stream for WithShutdown<S> {
+    type Item = (S::Item, Token);
+    type Error = S::Error;
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, S::Error> {
+        if self.shutdown.lock().register(self.id) {
+            if self.stream.take().is_some() {
+                info!("Shutdown requested, no longer accepting connections");
+            }
+            return Ok(Async::Ready(None));
+        }
+        let item = match self.stream {
+            Some(ref mut stream) => match stream.poll()? {
+                Async::Ready(Some(item)) => item,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            },
+            None => return Ok(Async::Ready(None)),
+        };
+        Ok(Async::Ready(Some((item, self.shutdown.token()))))
+    }
+}
+
+impl<S> Drop for WithShutdown<S> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shutdown.state.lock() {
+            state.tasks.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Future, Stream, Async};
+    use futures::future::poll_fn;
+    use futures::sync::{mpsc, oneshot};
+
+    use crate::test_util::{Task, Conn};
+    use crate::traits::ListenExt;
+    use super::{Shutdown, Token};
+
+    /// A connection which acknowledges shutdown and finishes when the
+    /// sender is used
+    fn conn(mut token: Token) -> (oneshot::Sender<()>, Conn) {
+        let (tx, mut rx) = oneshot::channel::<()>();
+        (tx, Box::new(poll_fn(move || {
+            if let Async::Ready(()) = token.poll()? {
+                token.acknowledge();
+            }
+            match rx.poll() {
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                _ => Ok(Async::Ready(())),
+            }
+        })))
+    }
+
+    #[test]
+    fn graceful() {
+        let shutdown = Shutdown::new();
+        let (tx, rx) = mpsc::unbounded();
+        let (senders_tx, senders_rx) = std::sync::mpsc::channel();
+        let mut listen = Task::new(rx.with_shutdown(&shutdown)
+            .map(move |((), token)| {
+                let (sender, conn) = conn(token);
+                senders_tx.send(sender).unwrap();
+                conn
+            })
+            .listen(10));
+        tx.unbounded_send(()).unwrap();
+        tx.unbounded_send(()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        let first = senders_rx.recv().unwrap();
+        let second = senders_rx.recv().unwrap();
+        assert_eq!(shutdown.connections(), 2);
+        assert_eq!(shutdown.acknowledged(), 0);
+        listen.woken();
+
+        shutdown.shutdown();
+        assert!(listen.woken());
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        // stream is dropped, so no more connections are accepted
+        assert!(tx.unbounded_send(()).is_err());
+        assert!(senders_rx.try_recv().is_err());
+        assert_eq!(shutdown.connections(), 2);
+        assert_eq!(shutdown.acknowledged(), 2);
+
+        // existing connections drain
+        first.send(()).unwrap();
+        assert!(listen.woken());
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(shutdown.connections(), 1);
+        assert_eq!(shutdown.acknowledged(), 1);
+
+        // listener finishes only after the last connection is closed
+        second.send(()).unwrap();
+        assert!(listen.woken());
+        assert_eq!(listen.poll(), Ok(Async::Ready(())));
+        assert_eq!(shutdown.connections(), 0);
+        assert_eq!(shutdown.acknowledged(), 0);
+    }
+
+    #[test]
+    fn token() {
+        let shutdown = Shutdown::new();
+        let mut token = Task::new(shutdown.token());
+        assert_eq!(token.poll(), Ok(Async::NotReady));
+        assert!(!token.get_mut().is_shutting_down());
+        shutdown.shutdown();
+        assert!(token.woken());
+        assert!(token.get_mut().is_shutting_down());
+        assert_eq!(token.poll(), Ok(Async::Ready(())));
+        token.get_mut().acknowledge();
+        token.get_mut().acknowledge();
+        assert_eq!(shutdown.acknowledged(), 1);
+        drop(token);
+        assert_eq!(shutdown.connections(), 0);
+        assert_eq!(shutdown.acknowledged(), 0);
+        // tokens created after shutdown are resolved immediately
+        assert_eq!(Task::new(shutdown.token()).poll(), Ok(Async::Ready(())));
+    }
+}
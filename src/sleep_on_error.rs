@@ -5,19 +5,7 @@ use futures::{Future, Stream, Async};
 use tokio::clock;
 use tokio::timer::Delay;
 
-
-/// This function defines errors that are per-connection. Which basically
-/// means that if we get this error from `accept()` system call it means
-/// next connection might be ready to be accepted.
-///
-/// All other errors will incur a timeout before next `accept()` is performed.
-/// The timeout is useful to handle resource exhaustion errors like ENFILE
-/// and EMFILE. Otherwise, could enter into tight loop.
-fn connection_error(e: &io::Error) -> bool {
-    e.kind() == io::ErrorKind::ConnectionRefused ||
-    e.kind() == io::ErrorKind::ConnectionAborted ||
-    e.kind() == io::ErrorKind::ConnectionReset
-}
+use crate::util::connection_error;
 
 
 /// A structure returned by `ListenExt::sleep_on_error`
@@ -33,8 +21,8 @@ pub struct SleepOnError<S> {
 pub fn new<S>(stream: S, delay: Duration) -> SleepOnError<S>
 {
     SleepOnError {
-        stream: stream,
-        delay: delay,
+        stream,
+        delay,
         timeout: None,
     }
 }
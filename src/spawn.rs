@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+
+use futures::{Future, Stream, Async};
+use futures::stream::FuturesUnordered;
+use futures::sync::oneshot;
+
+use crate::connection::{Conn, Outcome};
+
+
+type Finished<E> = Result<Outcome, (Option<SocketAddr>, E)>;
+
+/// Connections of `Listen` running as separate tasks on the executor
+///
+/// Each spawned task reports the result back through a oneshot channel.
+/// When listener is dropped, receiving ends are dropped too, so tasks are
+/// notified and drop their connections.
+pub struct Spawned<F: Future> {
+    spawn: fn(Detached<F>),
+    running: FuturesUnordered<oneshot::Receiver<Finished<F::Error>>>,
+}
+
+/// A connection future running in its own task
+pub struct Detached<F: Future> {
+    conn: Conn<F>,
+    tx: Option<oneshot::Sender<Finished<F::Error>>>,
+}
+
+fn spawn_detached<F>(task: Detached<F>)
+    where F: Future<Item=()> + Send + 'static,
+          F::Error: Send + 'static,
+{
+    tokio::spawn(task);
+}
+
+impl<F: Future<Item=()>> Spawned<F> {
+    pub fn new() -> Spawned<F>
+        where F: Send + 'static,
+              F::Error: Send + 'static,
+    {
+        Spawned {
+            spawn: spawn_detached::<F>,
+            running: FuturesUnordered::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, conn: Conn<F>) {
+        let (tx, rx) = oneshot::channel();
+        (self.spawn)(Detached { conn, tx: Some(tx) });
+        self.running.push(rx);
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+}
+
+impl<F: Future<Item=()>> Future for Detached<F> {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Result<Async<()>, ()> {
+        let result = {
+            let tx = self.tx.as_mut().expect("not polled after completion");
+            if let Ok(Async::Ready(())) = tx.poll_cancel() {
+                // listener is dropped, so connection should be dropped too
+                return Ok(Async::Ready(()));
+            }
+            match self.conn.poll() {
+                Ok(Async::Ready(outcome)) => Ok(outcome),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => Err(e),
+            }
+        };
+        let tx = self.tx.take().expect("not polled after completion");
+        tx.send(result).ok();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<F: Future<Item=()>> Stream for Spawned<F> {
+    type Item = Outcome;
+    type Error = (Option<SocketAddr>, F::Error);
+    fn poll(&mut self) -> Result<Async<Option<Outcome>>, Self::Error> {
+        match self.running.poll() {
+            Ok(Async::Ready(Some(Ok(outcome)))) => {
+                Ok(Async::Ready(Some(outcome)))
+            }
+            Ok(Async::Ready(Some(Err(e)))) => Err(e),
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(oneshot::Canceled) => {
+                // task is dropped without finishing the connection,
+                // usually because executor is shutting down
+                debug!("Spawned connection task has been dropped");
+                Ok(Async::Ready(Some(Outcome::Done)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::{Future, Async};
+    use futures::future::{lazy, poll_fn};
+    use futures::sync::mpsc;
+    use tokio::clock;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    use crate::test_util::Conn;
+    use crate::traits::ListenExt;
+
+    /// Counts running connections and maximum number of them
+    #[derive(Clone, Default)]
+    struct Counter {
+        running: Arc<AtomicUsize>,
+        max: Arc<AtomicUsize>,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    struct Running(Counter);
+
+    impl Counter {
+        fn start(&self) -> Running {
+            let num = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(num, Ordering::SeqCst);
+            Running(self.clone())
+        }
+    }
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            self.0.running.fetch_sub(1, Ordering::SeqCst);
+            self.0.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// A connection which runs for the specified time
+    fn conn(counter: &Counter, millis: u64) -> Conn {
+        let counter = counter.clone();
+        Box::new(lazy(move || {
+            let running = counter.start();
+            Delay::new(clock::now() + Duration::from_millis(millis))
+                .map(move |()| drop(running))
+                .map_err(|e| panic!("timer error: {}", e))
+        }))
+    }
+
+    #[test]
+    fn spawned() {
+        let counter = Counter::default();
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = rx.listen(2);
+        listen.spawn_connections();
+        let stats = listen.stats();
+        for _ in 0..5 {
+            tx.unbounded_send(conn(&counter, 5)).unwrap();
+        }
+        tx.unbounded_send(Box::new(futures::future::err(()))).unwrap();
+        drop(tx);
+        Runtime::new().unwrap().block_on(listen).unwrap();
+        assert_eq!(counter.max.load(Ordering::SeqCst), 2);
+        assert_eq!(counter.dropped.load(Ordering::SeqCst), 5);
+        assert_eq!(stats.accepted(), 6);
+        assert_eq!(stats.completed(), 6);
+        assert_eq!(stats.errored(), 1);
+    }
+
+    #[test]
+    fn dropping_listener_drops_connections() {
+        let counter = Counter::default();
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = rx.listen(10);
+        listen.spawn_connections();
+        for _ in 0..3 {
+            let counter = counter.clone();
+            let mut running = None;
+            tx.unbounded_send(Box::new(poll_fn(move || {
+                running.get_or_insert_with(|| counter.start());
+                Ok(Async::NotReady)
+            }))).unwrap();
+        }
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(lazy(move || {
+            assert_eq!(listen.poll(), Ok(Async::NotReady));
+            assert_eq!(listen.stats().active(), 3);
+            // let spawned tasks run, then drop the listener
+            Delay::new(clock::now() + Duration::from_millis(10))
+                .map(move |()| drop(listen))
+        })).unwrap();
+        assert_eq!(counter.running.load(Ordering::SeqCst), 3);
+        runtime.run().unwrap();
+        assert_eq!(counter.running.load(Ordering::SeqCst), 0);
+        assert_eq!(counter.dropped.load(Ordering::SeqCst), 3);
+    }
+}
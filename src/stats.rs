@@ -0,0 +1,250 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::clock;
+
+use crate::connection::Outcome;
+
+
+struct Inner {
+    active: AtomicUsize,
+    accepted: AtomicUsize,
+    completed: AtomicUsize,
+    errored: AtomicUsize,
+    timed_out: AtomicUsize,
+    panicked: AtomicUsize,
+    limit: Mutex<LimitTime>,
+}
+
+struct LimitTime {
+    total: Duration,
+    since: Option<Instant>,
+}
+
+/// Live statistics of the `Listen` future
+///
+/// Returned by `Listen::stats`. It's cheap to clone and can be read from
+/// any thread or task while listener is running.
+///
+/// Every accepted connection is either active or completed, i.e.
+/// `accepted() == active() + completed()`. Errored, timed out and
+/// panicked connections are counted both in `completed()` and in the
+/// respective counter.
+#[derive(Clone)]
+pub struct Stats {
+    inner: Arc<Inner>,
+}
+
+impl Stats {
+    pub(crate) fn new() -> Stats {
+        Stats {
+            inner: Arc::new(Inner {
+                active: AtomicUsize::new(0),
+                accepted: AtomicUsize::new(0),
+                completed: AtomicUsize::new(0),
+                errored: AtomicUsize::new(0),
+                timed_out: AtomicUsize::new(0),
+                panicked: AtomicUsize::new(0),
+                limit: Mutex::new(LimitTime {
+                    total: Duration::new(0, 0),
+                    since: None,
+                }),
+            }),
+        }
+    }
+
+    pub(crate) fn accepted_one(&self) {
+        self.inner.accepted.fetch_add(1, Ordering::Relaxed);
+        self.inner.active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn completed_one(&self, outcome: Option<Outcome>) {
+        match outcome {
+            Some(Outcome::Done) => {}
+            Some(Outcome::TimedOut) => {
+                self.inner.timed_out.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(Outcome::Panicked) => {
+                self.inner.panicked.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                self.inner.errored.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.inner.completed.fetch_add(1, Ordering::Relaxed);
+        self.inner.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Connections dropped without being finished (e.g. listener itself
+    /// is dropped)
+    pub(crate) fn dropped(&self, num: usize) {
+        self.inner.completed.fetch_add(num, Ordering::Relaxed);
+        self.inner.active.fetch_sub(num, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_at_limit(&self, value: bool) {
+        let mut limit = self.inner.limit.lock()
+            .expect("stats are not poisoned");
+        match (value, limit.since) {
+            (true, None) => limit.since = Some(clock::now()),
+            (false, Some(since)) => {
+                limit.total += clock::now() - since;
+                limit.since = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Number of connections that are currently being processed
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::Relaxed)
+    }
+
+    /// Total number of connections accepted so far
+    pub fn accepted(&self) -> usize {
+        self.inner.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Total number of connections finished so far (including errored ones)
+    pub fn completed(&self) -> usize {
+        self.inner.completed.load(Ordering::Relaxed)
+    }
+
+    /// Total number of connections which future finished with an error
+    pub fn errored(&self) -> usize {
+        self.inner.errored.load(Ordering::Relaxed)
+    }
+
+    /// Total number of connections closed by `max_connection_age` or
+    /// `idle_timeout`
+    pub fn timed_out(&self) -> usize {
+        self.inner.timed_out.load(Ordering::Relaxed)
+    }
+
+    /// Total number of connections which future has panicked
+    ///
+    /// Panic is caught and logged, and connection is closed, other
+    /// connections continue to work.
+    pub fn panicked(&self) -> usize {
+        self.inner.panicked.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent with `max_connections` active connections
+    ///
+    /// While limit is reached no new connections are accepted, so if
+    /// this value grows steadily you probably need to raise the limit.
+    pub fn time_at_limit(&self) -> Duration {
+        let limit = self.inner.limit.lock().expect("stats are not poisoned");
+        match limit.since {
+            Some(since) => limit.total + (clock::now() - since),
+            None => limit.total,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use futures::Async;
+    use futures::sync::mpsc;
+    use tokio::runtime::current_thread::Runtime;
+
+    use crate::test_util::{Task, Conn, conn, panicking};
+    use crate::traits::ListenExt;
+    use super::Stats;
+
+    fn check(stats: &Stats, active: usize, completed: usize) {
+        assert_eq!(stats.active(), active);
+        assert_eq!(stats.completed(), completed);
+        assert_eq!(stats.accepted(), stats.active() + stats.completed());
+    }
+
+    #[test]
+    fn counters() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = Task::new(rx.listen(10));
+        let stats = listen.get_mut().stats();
+        let (ok, ok_conn) = conn();
+        let (err, err_conn) = conn();
+        tx.unbounded_send(ok_conn).unwrap();
+        tx.unbounded_send(err_conn).unwrap();
+        tx.unbounded_send(panicking()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        check(&stats, 2, 1);
+        assert_eq!(stats.panicked(), 1);
+
+        drop(err);
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        check(&stats, 1, 2);
+        assert_eq!(stats.errored(), 1);
+
+        ok.send(()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        check(&stats, 0, 3);
+        assert_eq!(stats.accepted(), 3);
+        assert_eq!((stats.errored(), stats.panicked()), (1, 1));
+        assert_eq!(stats.timed_out(), 0);
+
+        drop(tx);
+        assert_eq!(listen.poll(), Ok(Async::Ready(())));
+    }
+
+    #[test]
+    fn dropped_listener() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = Task::new(rx.listen(10));
+        let stats = listen.get_mut().stats();
+        let (_keep, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        check(&stats, 1, 0);
+        drop(listen);
+        check(&stats, 0, 1);
+    }
+
+    #[test]
+    fn timed_out() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = rx.listen(10);
+        listen.idle_timeout(Duration::from_millis(10));
+        let stats = listen.stats();
+        let (_keep, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        drop(tx);
+        Runtime::new().unwrap().block_on(listen).unwrap();
+        check(&stats, 0, 1);
+        assert_eq!(stats.timed_out(), 1);
+        assert_eq!(stats.errored(), 0);
+    }
+
+    #[test]
+    fn time_at_limit() {
+        let (tx, rx) = mpsc::unbounded::<Conn>();
+        let mut listen = Task::new(rx.listen(1));
+        let stats = listen.get_mut().stats();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        assert_eq!(stats.time_at_limit(), Duration::new(0, 0));
+
+        let (first, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        sleep(Duration::from_millis(20));
+        assert!(stats.time_at_limit() >= Duration::from_millis(20));
+
+        first.send(()).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        let total = stats.time_at_limit();
+        sleep(Duration::from_millis(10));
+        assert_eq!(stats.time_at_limit(), total);
+
+        // time is accumulated
+        let (_second, c) = conn();
+        tx.unbounded_send(c).unwrap();
+        assert_eq!(listen.poll(), Ok(Async::NotReady));
+        sleep(Duration::from_millis(10));
+        assert!(stats.time_at_limit() >= total + Duration::from_millis(10));
+    }
+}
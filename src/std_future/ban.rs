@@ -0,0 +1,466 @@
+use std::cmp::min;
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures03::Stream;
+use pin_project_lite::pin_project;
+
+use crate::peer::PeerAddr;
+use crate::util::mask;
+
+
+#[derive(Clone, Copy)]
+struct Rate {
+    max: u32,
+    window: Duration,
+}
+
+#[derive(Default)]
+struct Window {
+    start: Option<Instant>,
+    events: u32,
+}
+
+struct Peer {
+    connections: Window,
+    errors: Window,
+    offences: u32,
+    banned_until: Option<Instant>,
+    /// Time when offences are forgotten
+    clean_at: Instant,
+    last_seen: Instant,
+}
+
+struct Inner {
+    connection_rate: Option<Rate>,
+    error_rate: Option<Rate>,
+    ban_time: Duration,
+    max_ban_time: Duration,
+    max_peers: usize,
+    ipv6_prefix: u8,
+    peers: HashMap<IpAddr, Peer>,
+    /// Peers ordered by the time they were seen last
+    seen: BTreeSet<(Instant, IpAddr)>,
+    bans: usize,
+    rejected: usize,
+}
+
+/// A table of temporarily banned peers
+///
+/// Peers which open connections too fast (see `connection_rate`) or
+/// whose connections fail too often (see `error_rate`, errors are
+/// reported by connection handlers via `report_error`) are banned for
+/// `ban_time`, and connections from them are closed by `BanPeers` stage
+/// (see `ListenExt::ban_peers`). Each next ban of the same peer is twice
+/// as long (up to `max_ban_time`), peer's offences are forgotten after
+/// it behaves for `max_ban_time`.
+///
+/// ```rust,ignore
+/// let mut bans = BanTable::new();
+/// bans.connection_rate(100, Duration::from_secs(10));
+/// bans.error_rate(10, Duration::from_secs(60));
+/// let handle = bans.clone();
+/// BindMany::new(address_stream)
+///     .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+///     .ban_peers(&bans)
+///     .map(move |socket| {
+///         let bans = handle.clone();
+///         async move {
+///             let peer = socket.peer_addr();
+///             if let Err(e) = Proto::new(socket).await {
+///                 if let Ok(peer) = peer {
+///                     bans.report_error(peer.ip());
+///                 }
+///             }
+///         }
+///     })
+///     .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+///     .await;
+/// ```
+///
+/// IPv6 addresses are grouped by a prefix (`/64` by default, see
+/// `ipv6_prefix`), because a single client usually owns the whole
+/// network. Methods of the table accept any address of the network.
+///
+/// The table tracks at most `max_peers` networks (default is `10000`),
+/// when it's full, the peer seen least recently is forgotten to make room
+/// for a new one. Banned peers are never forgotten before the ban
+/// expires, so new peers aren't tracked only when all tracked ones are
+/// banned.
+#[derive(Clone)]
+pub struct BanTable {
+    inner: Arc<Mutex<Inner>>,
+}
+
+pin_project! {
+    /// A structure returned by `ListenExt::ban_peers`
+    ///
+    /// This is a stream which closes sockets from peers banned in the
+    /// `BanTable` and yields other sockets unchanged.
+    pub struct BanPeers<S> {
+        #[pin]
+        stream: S,
+        table: BanTable,
+    }
+}
+
+impl Window {
+    /// Registers an event, returns `true` if rate is exceeded
+    fn hit(&mut self, rate: &Rate, now: Instant) -> bool {
+        match self.start {
+            Some(start) if now < start + rate.window => {}
+            _ => {
+                self.start = Some(now);
+                self.events = 0;
+            }
+        }
+        self.events = self.events.saturating_add(1);
+        self.events > rate.max
+    }
+}
+
+impl Peer {
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| now < until)
+    }
+}
+
+impl Inner {
+    fn peer(&mut self, addr: IpAddr, now: Instant) -> Option<&mut Peer> {
+        if !self.peers.contains_key(&addr) &&
+            self.peers.len() >= self.max_peers &&
+            !self.forget_oldest(now)
+        {
+            return None;
+        }
+        let peer = self.peers.entry(addr).or_insert_with(|| Peer {
+            connections: Window::default(),
+            errors: Window::default(),
+            offences: 0,
+            banned_until: None,
+            clean_at: now,
+            last_seen: now,
+        });
+        self.seen.remove(&(peer.last_seen, addr));
+        peer.last_seen = now;
+        self.seen.insert((now, addr));
+        Some(peer)
+    }
+    /// Forgets the peer seen least recently which isn't banned, returns
+    /// `false` if all peers are banned
+    fn forget_oldest(&mut self, now: Instant) -> bool {
+        let peers = &self.peers;
+        let oldest = self.seen.iter()
+            .find(|(_, addr)| !peers[addr].is_banned(now))
+            .cloned();
+        match oldest {
+            Some(key) => {
+                self.seen.remove(&key);
+                self.peers.remove(&key.1);
+                true
+            }
+            None => false,
+        }
+    }
+    fn remove(&mut self, addr: IpAddr) {
+        if let Some(peer) = self.peers.remove(&addr) {
+            self.seen.remove(&(peer.last_seen, addr));
+        }
+    }
+    fn ban(&mut self, addr: IpAddr, now: Instant) {
+        let (base, max) = (self.ban_time, self.max_ban_time);
+        if let Some(peer) = self.peer(addr, now) {
+            if now >= peer.clean_at {
+                peer.offences = 0;
+            }
+            let factor = 1u32.checked_shl(peer.offences).unwrap_or(u32::MAX);
+            let time = min(base.saturating_mul(factor), max);
+            peer.offences = peer.offences.saturating_add(1);
+            peer.banned_until = Some(now + time);
+            peer.clean_at = now + time + max;
+            info!("Peer {} is banned for {:?}", addr, time);
+        }
+        self.bans += 1;
+    }
+}
+
+impl Default for BanTable {
+    fn default() -> BanTable {
+        BanTable::new()
+    }
+}
+
+impl BanTable {
+    /// Create a table with no limits set (so no peer is banned)
+    pub fn new() -> BanTable {
+        BanTable {
+            inner: Arc::new(Mutex::new(Inner {
+                connection_rate: None,
+                error_rate: None,
+                ban_time: Duration::from_secs(60),
+                max_ban_time: Duration::from_secs(3600),
+                max_peers: 10000,
+                ipv6_prefix: 64,
+                peers: HashMap::new(),
+                seen: BTreeSet::new(),
+                bans: 0,
+                rejected: 0,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("ban table is not poisoned")
+    }
+
+    /// Bans peers opening more than `max` connections within `window`
+    pub fn connection_rate(&mut self, max: u32, window: Duration)
+        -> &mut Self
+    {
+        self.lock().connection_rate = Some(Rate { max, window });
+        self
+    }
+
+    /// Bans peers reporting more than `max` errors within `window`
+    pub fn error_rate(&mut self, max: u32, window: Duration) -> &mut Self {
+        self.lock().error_rate = Some(Rate { max, window });
+        self
+    }
+
+    /// Sets duration of the first ban and the maximum duration of
+    /// repeated bans (defaults are one minute and one hour)
+    pub fn ban_time(&mut self, first: Duration, max: Duration) -> &mut Self {
+        {
+            let mut inner = self.lock();
+            inner.ban_time = first;
+            inner.max_ban_time = max;
+        }
+        self
+    }
+
+    /// Sets maximum number of peers tracked (default is `10000`)
+    pub fn max_peers(&mut self, max_peers: usize) -> &mut Self {
+        self.lock().max_peers = max_peers;
+        self
+    }
+
+    /// Sets length of the IPv6 prefix which is considered a single peer
+    ///
+    /// By default it's `64`, use `128` to track each address separately.
+    /// Should be set before the table is used.
+    pub fn ipv6_prefix(&mut self, bits: u8) -> &mut Self {
+        self.lock().ipv6_prefix = bits;
+        self
+    }
+
+    /// Reports that connection from the peer has failed
+    pub fn report_error(&self, addr: IpAddr) {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let addr = mask(addr, inner.ipv6_prefix);
+        let rate = match inner.error_rate {
+            Some(rate) => rate,
+            None => return,
+        };
+        let exceeded = match inner.peer(addr, now) {
+            Some(peer) => !peer.is_banned(now) && peer.errors.hit(&rate, now),
+            None => false,
+        };
+        if exceeded {
+            inner.ban(addr, now);
+        }
+    }
+
+    /// Registers a new connection, returns `false` if peer is banned
+    fn check_connection(&self, addr: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let addr = mask(addr, inner.ipv6_prefix);
+        let rate = inner.connection_rate;
+        let (banned, exceeded) = match inner.peer(addr, now) {
+            Some(peer) if peer.is_banned(now) => (true, false),
+            Some(peer) => {
+                (false, rate.is_some_and(|r| peer.connections.hit(&r, now)))
+            }
+            None => (false, false),
+        };
+        if exceeded {
+            inner.ban(addr, now);
+        }
+        if banned || exceeded {
+            inner.rejected += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Returns `true` if the peer is currently banned
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        let now = Instant::now();
+        let inner = self.lock();
+        let addr = mask(addr, inner.ipv6_prefix);
+        inner.peers.get(&addr).is_some_and(|p| p.is_banned(now))
+    }
+
+    /// Returns currently banned peers and time left for each of them
+    ///
+    /// IPv6 peers are returned as the first address of the network.
+    pub fn banned(&self) -> Vec<(IpAddr, Duration)> {
+        let now = Instant::now();
+        self.lock().peers.iter()
+            .filter_map(|(addr, peer)| {
+                peer.banned_until
+                    .filter(|&until| now < until)
+                    .map(|until| (*addr, until - now))
+            })
+            .collect()
+    }
+
+    /// Removes the ban and forgets offences of the peer
+    pub fn unban(&self, addr: IpAddr) {
+        let mut inner = self.lock();
+        let addr = mask(addr, inner.ipv6_prefix);
+        inner.remove(addr);
+    }
+
+    /// Returns number of peers tracked in the table
+    pub fn peers(&self) -> usize {
+        self.lock().peers.len()
+    }
+
+    /// Total number of bans issued
+    pub fn bans(&self) -> usize {
+        self.lock().bans
+    }
+
+    /// Total number of connections closed because peer is banned
+    pub fn rejected(&self) -> usize {
+        self.lock().rejected
+    }
+}
+
+pub fn new<S>(stream: S, table: &BanTable) -> BanPeers<S>
+    where S: Stream,
+          S::Item: PeerAddr,
+{
+    BanPeers {
+        stream,
+        table: table.clone(),
+    }
+}
+
+impl<S> Stream for BanPeers<S>
+    where S: Stream,
+          S::Item: PeerAddr,
+{
+    type Item = S::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Option<Self::Item>>
+    {
+        let mut this = self.project();
+        loop {
+            let socket = match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(socket)) => socket,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            match socket.peer_addr() {
+                Ok(addr) if !this.table.check_connection(addr.ip()) => {
+                    debug!("Connection from banned peer {}", addr);
+                }
+                // sockets without address can't be banned
+                _ => return Poll::Ready(Some(socket)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use super::BanTable;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn table() -> BanTable {
+        let mut bans = BanTable::new();
+        // every error is an offence
+        bans.error_rate(0, Duration::from_secs(60));
+        bans
+    }
+
+    #[test]
+    fn ipv6_network() {
+        let bans = table();
+        bans.report_error(ip("2001:db8:0:1::1"));
+        assert!(bans.is_banned(ip("2001:db8:0:1::1")));
+        assert!(bans.is_banned(ip("2001:db8:0:1:ffff::2")));
+        assert!(!bans.check_connection(ip("2001:db8:0:1::3")));
+        assert!(!bans.is_banned(ip("2001:db8:0:2::1")));
+        assert!(bans.check_connection(ip("2001:db8:0:2::1")));
+        assert_eq!(bans.banned()[0].0, ip("2001:db8:0:1::"));
+        bans.unban(ip("2001:db8:0:1::5"));
+        assert!(!bans.is_banned(ip("2001:db8:0:1::1")));
+    }
+
+    #[test]
+    fn ipv6_prefix() {
+        let mut bans = table();
+        bans.ipv6_prefix(128);
+        bans.report_error(ip("2001:db8::1"));
+        assert!(bans.is_banned(ip("2001:db8::1")));
+        assert!(!bans.is_banned(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn ipv4_mapped() {
+        let bans = table();
+        bans.report_error(ip("::ffff:10.0.0.1"));
+        assert!(bans.is_banned(ip("10.0.0.1")));
+        assert!(!bans.is_banned(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn full_table_forgets_oldest() {
+        let mut bans = table();
+        bans.max_peers(2);
+        assert!(bans.check_connection(ip("10.0.0.1")));
+        assert!(bans.check_connection(ip("10.0.0.2")));
+        // touching the first peer makes the second one the oldest
+        assert!(bans.check_connection(ip("10.0.0.1")));
+        bans.report_error(ip("10.0.0.3"));
+        assert_eq!(bans.peers(), 2);
+        assert!(bans.is_banned(ip("10.0.0.3")));
+        let peers = bans.lock().peers.keys().cloned().collect::<Vec<_>>();
+        assert!(peers.contains(&ip("10.0.0.1")));
+        assert!(!peers.contains(&ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn full_table_keeps_bans() {
+        let mut bans = table();
+        bans.max_peers(2);
+        bans.report_error(ip("10.0.0.1"));
+        assert!(bans.check_connection(ip("10.0.0.2")));
+        // the oldest peer is banned, so the next one is forgotten
+        bans.report_error(ip("10.0.0.3"));
+        assert!(bans.is_banned(ip("10.0.0.1")));
+        assert!(bans.is_banned(ip("10.0.0.3")));
+        // all peers are banned, the new one isn't tracked
+        bans.report_error(ip("10.0.0.4"));
+        assert!(!bans.is_banned(ip("10.0.0.4")));
+        assert!(bans.check_connection(ip("10.0.0.4")));
+        assert!(bans.is_banned(ip("10.0.0.1")));
+        assert!(bans.is_banned(ip("10.0.0.3")));
+        assert_eq!(bans.peers(), 2);
+        assert_eq!(bans.bans(), 2);
+    }
+}
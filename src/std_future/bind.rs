@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures03::Stream;
+use pin_project_lite::pin_project;
+
+use crate::std_future::runtime::{Timer, Bind, Listener};
+#[cfg(feature = "rt-tokio")]
+use crate::std_future::runtime::Tokio;
+
+
+pin_project! {
+    /// This stream listens many sockets, like `BindMany` from the crate root
+    ///
+    /// It receives a stream of lists of addresses as an input.
+    /// When a new value received on a stream it adapts:
+    ///
+    /// 1. Removes sockets not in set we're already received (already
+    ///    established connections aren't interfered in any way)
+    /// 2. Adds sockets to set which wasn't listened before
+    ///
+    /// Instead of failing on bind error it logs the error and retries in
+    /// a second (you can change the delay using `BindMany::retry_interval`).
+    /// Stream ends when the stream of addresses ends.
+    ///
+    /// Errors of `accept()` are returned as items, use
+    /// `ListenExt::sleep_on_error` to handle them.
+    ///
+    /// Sockets and timers are created by the runtime `R`, see
+    /// `BindMany::with_runtime`.
+    pub struct BindMany<S, R>
+        where R: Timer,
+              R: Bind,
+    {
+        #[pin]
+        addresses: S,
+        runtime: R,
+        retry_interval: Duration,
+        retry_timer: Option<(Pin<Box<R::Sleep>>, Vec<SocketAddr>)>,
+        inputs: HashMap<SocketAddr, R::Listener>,
+    }
+}
+
+#[cfg(feature = "rt-tokio")]
+impl<S> BindMany<S, Tokio> {
+    /// Create a new instance which uses tokio sockets
+    pub fn new(s: S) -> BindMany<S, Tokio> {
+        BindMany::with_runtime(s, Tokio)
+    }
+}
+
+impl<S, R: Timer + Bind> BindMany<S, R> {
+    /// Create a new instance which uses sockets and timers of `runtime`
+    ///
+    /// For example, use `AsyncIo` for smol or async-std.
+    pub fn with_runtime(s: S, runtime: R) -> BindMany<S, R> {
+        BindMany {
+            addresses: s,
+            runtime,
+            retry_interval: Duration::new(1, 0),
+            retry_timer: None,
+            inputs: HashMap::new(),
+        }
+    }
+
+    /// Sets the retry interval
+    ///
+    /// Each time binding socket fails (including the first one on start)
+    /// instead of immediately failing we log the error and sleep this
+    /// interval to retry (by default 1 second).
+    pub fn retry_interval(&mut self, interval: Duration) -> &mut Self {
+        self.retry_interval = interval;
+        self
+    }
+}
+
+impl<S, R: Timer + Bind> Stream for BindMany<S, R>
+    where S: Stream,
+        S::Item: IntoIterator<Item=SocketAddr>,
+{
+    type Item = io::Result<<R::Listener as Listener>::Socket>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Option<Self::Item>>
+    {
+        let mut this = self.project();
+        loop {
+            match this.addresses.as_mut().poll_next(cx) {
+                Poll::Ready(None) => {
+                    info!("Listening stream reached end-of-stream condition");
+                    return Poll::Ready(None);
+                }
+                Poll::Ready(Some(new)) => {
+                    let mut old = mem::take(this.inputs);
+                    let mut backlog = Vec::new();
+                    for addr in new {
+                        if let Some(listener) = old.remove(&addr) {
+                            this.inputs.insert(addr, listener);
+                        } else {
+                            match this.runtime.bind(&addr) {
+                                Ok(l) => {
+                                    this.inputs.insert(addr, l);
+                                }
+                                Err(e) => {
+                                    backlog.push(addr);
+                                    error!("Error binding {:?}: {}, \
+                                        will retry in {:?}",
+                                        addr, e, this.retry_interval);
+                                }
+                            }
+                        }
+                    }
+                    if !backlog.is_empty() {
+                        *this.retry_timer = Some((
+                            Box::pin(this.runtime.sleep(*this.retry_interval)),
+                            backlog));
+                    } else {
+                        *this.retry_timer = None;
+                    }
+                }
+                Poll::Pending => break,
+            }
+        }
+        while let Some((ref mut timer, ref mut backlog)) = *this.retry_timer {
+            match timer.as_mut().poll(cx) {
+                Poll::Ready(_) => {
+                    for addr in mem::take(backlog) {
+                        match this.runtime.bind(&addr) {
+                            Ok(l) => {
+                                this.inputs.insert(addr, l);
+                            }
+                            Err(e) => {
+                                backlog.push(addr);
+                                // Lower level on retry
+                                debug!("Error binding {:?}: {}, \
+                                    will retry in {:?}",
+                                    addr, e, this.retry_interval);
+                            }
+                        }
+                    }
+                    if !backlog.is_empty() {
+                        *timer = Box::pin(
+                            this.runtime.sleep(*this.retry_interval));
+                        continue;  // need to poll timer
+                    }
+                    *this.retry_timer = None;
+                }
+                Poll::Pending => break,
+            }
+        }
+        for listener in this.inputs.values_mut() {
+            match listener.poll_accept(cx) {
+                Poll::Ready(Ok(socket)) => {
+                    return Poll::Ready(Some(Ok(socket)));
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => continue,
+            }
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(all(test, feature = "rt-tokio"))]
+mod test {
+    use std::io;
+    use std::net::{self, SocketAddr};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures03::{Stream, StreamExt};
+    use futures03::channel::mpsc;
+    use futures03::task::noop_waker;
+    use tokio1::net::TcpStream;
+    use tokio1::time::sleep;
+
+    use super::BindMany;
+
+    fn free_addr() -> SocketAddr {
+        net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    fn poll<S: Stream + Unpin>(stream: &mut S) -> Poll<Option<S::Item>> {
+        let waker = noop_waker();
+        stream.poll_next_unpin(&mut Context::from_waker(&waker))
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn rebind() {
+        let (a, b) = (free_addr(), free_addr());
+        let (tx, rx) = mpsc::unbounded();
+        let mut bind = BindMany::new(rx);
+        tx.unbounded_send(vec![a]).unwrap();
+        assert!(poll(&mut bind).is_pending());
+        let client = TcpStream::connect(a).await.unwrap();
+        let socket = bind.next().await.unwrap().unwrap();
+        assert_eq!(socket.peer_addr().unwrap(), client.local_addr().unwrap());
+
+        tx.unbounded_send(vec![b]).unwrap();
+        assert!(poll(&mut bind).is_pending());
+        let err = TcpStream::connect(a).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        let client = TcpStream::connect(b).await.unwrap();
+        let socket = bind.next().await.unwrap().unwrap();
+        assert_eq!(socket.peer_addr().unwrap(), client.local_addr().unwrap());
+        assert_eq!(socket.local_addr().unwrap(), b);
+
+        drop(tx);
+        assert!(bind.next().await.is_none());
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn retry_bind() {
+        let occupied = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = occupied.local_addr().unwrap();
+        let (tx, rx) = mpsc::unbounded();
+        let mut bind = BindMany::new(rx);
+        bind.retry_interval(Duration::from_millis(10));
+        tx.unbounded_send(vec![addr]).unwrap();
+        assert!(poll(&mut bind).is_pending());
+
+        drop(occupied);
+        sleep(Duration::from_millis(50)).await;
+        // address is bound again when the retry timer fires
+        assert!(poll(&mut bind).is_pending());
+        let client = TcpStream::connect(addr).await.unwrap();
+        let socket = bind.next().await.unwrap().unwrap();
+        assert_eq!(socket.peer_addr().unwrap(), client.local_addr().unwrap());
+    }
+}
@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+
+/// A network of IP addresses, like `10.0.0.0/8` or `2001:db8::/32`
+///
+/// IPv4 networks also match IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`),
+/// which are reported as peer addresses by sockets bound to `[::]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+/// Error parsing `Cidr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCidrError(());
+
+impl Cidr {
+    /// Create a network from an address and a prefix length
+    ///
+    /// Returns `None` if prefix is longer than the address. Bits of the
+    /// address after the prefix are ignored.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Cidr> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > max {
+            return None;
+        }
+        Some(Cidr { addr, prefix })
+    }
+
+    /// Returns `true` if address belongs to this network
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                matches(u32::from(net), u32::from(addr), self.prefix, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                matches(u128::from(net), u128::from(addr), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn matches<T>(net: T, addr: T, prefix: u8, bits: u8) -> bool
+    where T: Into<u128>,
+{
+    if prefix == 0 {
+        return true;
+    }
+    let shift = u32::from(bits - prefix);
+    (net.into() ^ addr.into()) >> shift == 0
+}
+
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+    fn from_str(s: &str) -> Result<Cidr, ParseCidrError> {
+        let (addr, prefix) = match s.find('/') {
+            Some(idx) => {
+                let prefix = s[idx+1..].parse()
+                    .map_err(|_| ParseCidrError(()))?;
+                (&s[..idx], Some(prefix))
+            }
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| ParseCidrError(()))?;
+        let prefix = prefix.unwrap_or(if addr.is_ipv4() { 32 } else { 128 });
+        Cidr::new(addr, prefix).ok_or(ParseCidrError(()))
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid CIDR network")
+    }
+}
+
+impl Error for ParseCidrError {}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use super::Cidr;
+
+    fn net(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(net("10.0.0.0/8"), Cidr::new(ip("10.0.0.0"), 8).unwrap());
+        assert_eq!(net("10.1.2.3"), Cidr::new(ip("10.1.2.3"), 32).unwrap());
+        assert_eq!(net("::1"), Cidr::new(ip("::1"), 128).unwrap());
+        assert_eq!(net("2001:db8::/32").to_string(), "2001:db8::/32");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/-1".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn contains_ipv4() {
+        let n = net("10.1.0.0/16");
+        assert!(n.contains(&ip("10.1.0.0")));
+        assert!(n.contains(&ip("10.1.255.255")));
+        assert!(!n.contains(&ip("10.2.0.1")));
+        assert!(!n.contains(&ip("::a01:1")));
+        assert!(n.contains(&ip("::ffff:10.1.2.3")));
+        assert!(net("10.1.2.3").contains(&ip("10.1.2.3")));
+        assert!(!net("10.1.2.3").contains(&ip("10.1.2.4")));
+        // bits after the prefix are ignored
+        assert!(net("10.1.2.3/8").contains(&ip("10.200.0.1")));
+    }
+
+    #[test]
+    fn contains_ipv6() {
+        let n = net("2001:db8::/32");
+        assert!(n.contains(&ip("2001:db8::1")));
+        assert!(n.contains(&ip("2001:db8:ffff::1")));
+        assert!(!n.contains(&ip("2001:db9::1")));
+        assert!(!n.contains(&ip("32.1.13.184")));
+        assert!(net("::1").contains(&ip("::1")));
+        assert!(!net("::1").contains(&ip("::2")));
+    }
+
+    #[test]
+    fn contains_all() {
+        assert!(net("0.0.0.0/0").contains(&ip("1.2.3.4")));
+        assert!(!net("0.0.0.0/0").contains(&ip("::1")));
+        assert!(net("::/0").contains(&ip("::1")));
+        assert!(!net("::/0").contains(&ip("1.2.3.4")));
+    }
+}
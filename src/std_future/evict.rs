@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures03::task::AtomicWaker;
+use pin_project_lite::pin_project;
+
+use crate::std_future::runtime::Timer;
+#[cfg(feature = "rt-tokio")]
+use crate::std_future::runtime::Tokio;
+
+
+struct Conn {
+    id: u64,
+    /// Only changed with the lock of `Tracker::state` held
+    idle_since: Mutex<Option<Instant>>,
+    evicted: AtomicBool,
+    /// Only changed with the lock of `Tracker::state` held
+    finished: AtomicBool,
+    waker: AtomicWaker,
+}
+
+struct State {
+    next_id: u64,
+    /// Idle connections ordered by the time they became idle
+    idle: BTreeMap<(Instant, u64), Arc<Conn>>,
+    /// `Listen` holds a connection until some connection is evicted
+    waiting: bool,
+    evicting: usize,
+    evicted: usize,
+}
+
+pub(crate) struct Tracker {
+    state: Mutex<State>,
+    /// Task of the `Listen` waiting for an idle connection
+    listener: AtomicWaker,
+}
+
+/// Evicts idle connections when `Listen` is at the limit
+///
+/// Connection futures wrapped by `Eviction::wrap` receive an `IdleHandle`
+/// to mark connection idle (e.g. keep-alive connection waiting for the
+/// next request) or busy. When `Listen` (attached with
+/// `Listen::evict_idle`) is at the limit and there is a new connection to
+/// accept, the connection which is idle for the longest time is notified
+/// (see `IdleHandle::evicted`) and is dropped if it doesn't finish within
+/// a grace period. One connection is evicted at a time.
+///
+/// ```rust,ignore
+/// let eviction = Eviction::new(Duration::from_secs(1));
+/// let wrapper = eviction.clone();
+/// let mut listen = BindMany::new(address_stream)
+///     .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+///     .map(move |socket| wrapper.wrap(|conn| async move {
+///         loop {
+///             conn.idle();
+///             let request = select(read_request(&socket), conn.evicted());
+///             conn.busy();
+///             // ...
+///         }
+///     }))
+///     .listen(MAX_SIMULTANEOUS_CONNECTIONS);
+/// listen.evict_idle(&eviction);
+/// listen.await;
+/// ```
+#[derive(Clone)]
+pub struct Eviction<T> {
+    tracker: Arc<Tracker>,
+    timer: T,
+    grace: Duration,
+}
+
+/// A handle to mark connection idle or busy
+///
+/// Connection is considered busy until `idle` is called.
+#[derive(Clone)]
+pub struct IdleHandle {
+    conn: Arc<Conn>,
+    tracker: Arc<Tracker>,
+}
+
+/// A future that resolves when connection is chosen to be evicted
+pub struct Evicted {
+    conn: Arc<Conn>,
+}
+
+struct Registration {
+    conn: Arc<Conn>,
+    tracker: Arc<Tracker>,
+}
+
+pin_project! {
+    /// A connection future returned by `Eviction::wrap`
+    ///
+    /// Resolves when the wrapped future resolves or when grace period
+    /// after eviction expires (wrapped future is dropped in this case).
+    pub struct Evictable<F, T>
+        where T: Timer,
+    {
+        #[pin]
+        future: F,
+        registration: Registration,
+        timer: T,
+        grace: Duration,
+        deadline: Option<Pin<Box<T::Sleep>>>,
+    }
+}
+
+#[cfg(feature = "rt-tokio")]
+impl Eviction<Tokio> {
+    /// Create an eviction handle which uses tokio timers
+    ///
+    /// Evicted connections are dropped after `grace` period.
+    pub fn new(grace: Duration) -> Eviction<Tokio> {
+        Eviction::with_timer(Tokio, grace)
+    }
+}
+
+impl<T: Timer + Clone> Eviction<T> {
+    /// Create an eviction handle which uses timers of the runtime
+    pub fn with_timer(timer: T, grace: Duration) -> Eviction<T> {
+        Eviction {
+            tracker: Arc::new(Tracker {
+                state: Mutex::new(State {
+                    next_id: 0,
+                    idle: BTreeMap::new(),
+                    waiting: false,
+                    evicting: 0,
+                    evicted: 0,
+                }),
+                listener: AtomicWaker::new(),
+            }),
+            timer,
+            grace,
+        }
+    }
+
+    /// Wraps a connection future, so it can be evicted
+    pub fn wrap<F, R>(&self, f: F) -> Evictable<R, T>
+        where F: FnOnce(IdleHandle) -> R,
+              R: Future<Output=()>,
+    {
+        let id = {
+            let mut state = self.tracker.lock();
+            state.next_id += 1;
+            state.next_id
+        };
+        let conn = Arc::new(Conn {
+            id,
+            idle_since: Mutex::new(None),
+            evicted: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        Evictable {
+            future: f(IdleHandle {
+                conn: conn.clone(),
+                tracker: self.tracker.clone(),
+            }),
+            registration: Registration {
+                conn,
+                tracker: self.tracker.clone(),
+            },
+            timer: self.timer.clone(),
+            grace: self.grace,
+            deadline: None,
+        }
+    }
+
+    /// Total number of connections evicted
+    pub fn evicted(&self) -> usize {
+        self.tracker.lock().evicted
+    }
+
+    pub(crate) fn tracker(&self) -> &Arc<Tracker> {
+        &self.tracker
+    }
+}
+
+impl Tracker {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("eviction is not poisoned")
+    }
+
+    /// Notifies the connection which is idle for the longest time, unless
+    /// some connection is being evicted already
+    ///
+    /// If there is no idle connection, the task is woken up when some
+    /// connection becomes idle.
+    pub(crate) fn evict_idle(&self, cx: &mut Context<'_>) {
+        let mut state = self.lock();
+        self.listener.register(cx.waker());
+        state.waiting = true;
+        if state.evicting > 0 {
+            return;
+        }
+        if let Some(((since, _), conn)) = state.idle.pop_first() {
+            debug!("Evicting connection idle for {:?}", since.elapsed());
+            conn.evicted.store(true, Ordering::SeqCst);
+            conn.waker.wake();
+            state.evicting += 1;
+            state.evicted += 1;
+        }
+    }
+
+    /// Marks that `Listen` doesn't hold a connection any more
+    pub(crate) fn stop_waiting(&self) {
+        self.lock().waiting = false;
+    }
+}
+
+impl IdleHandle {
+    /// Marks connection as idle
+    ///
+    /// Connection can be evicted until `busy` is called. Calling this
+    /// method again doesn't reset the time connection is idle for.
+    pub fn idle(&self) {
+        let conn = &self.conn;
+        let mut state = self.tracker.lock();
+        if conn.finished.load(Ordering::SeqCst) ||
+            conn.evicted.load(Ordering::SeqCst)
+        {
+            return;
+        }
+        let mut since = conn.idle_since.lock()
+            .expect("connection is not poisoned");
+        if since.is_none() {
+            let now = Instant::now();
+            *since = Some(now);
+            state.idle.insert((now, conn.id), conn.clone());
+            if state.waiting && state.evicting == 0 {
+                self.tracker.listener.wake();
+            }
+        }
+    }
+
+    /// Marks connection as busy, so it isn't evicted
+    ///
+    /// Note: connection that is already notified is dropped after the
+    /// grace period anyway.
+    pub fn busy(&self) {
+        let mut state = self.tracker.lock();
+        let since = self.conn.idle_since.lock()
+            .expect("connection is not poisoned").take();
+        if let Some(since) = since {
+            state.idle.remove(&(since, self.conn.id));
+        }
+    }
+
+    /// Returns `true` if connection has been chosen to be evicted
+    pub fn is_evicted(&self) -> bool {
+        self.conn.evicted.load(Ordering::SeqCst)
+    }
+
+    /// Returns a future which resolves when connection is chosen to be
+    /// evicted, connection should be closed gracefully at this point
+    pub fn evicted(&self) -> Evicted {
+        Evicted { conn: self.conn.clone() }
+    }
+}
+
+impl Future for Evicted {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.conn.evicted.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        self.conn.waker.register(cx.waker());
+        if self.conn.evicted.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut state = self.tracker.lock();
+        self.conn.finished.store(true, Ordering::SeqCst);
+        if self.conn.evicted.load(Ordering::SeqCst) {
+            state.evicting -= 1;
+        } else {
+            let since = self.conn.idle_since.lock()
+                .expect("connection is not poisoned").take();
+            if let Some(since) = since {
+                state.idle.remove(&(since, self.conn.id));
+            }
+        }
+    }
+}
+
+impl<F, T> Future for Evictable<F, T>
+    where F: Future<Output=()>,
+          T: Timer,
+{
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.project();
+        if this.future.poll(cx).is_ready() {
+            return Poll::Ready(());
+        }
+        let conn = &this.registration.conn;
+        if this.deadline.is_none() {
+            conn.waker.register(cx.waker());
+            if !conn.evicted.load(Ordering::SeqCst) {
+                return Poll::Pending;
+            }
+            *this.deadline = Some(Box::pin(this.timer.sleep(*this.grace)));
+        }
+        let deadline = this.deadline.as_mut().expect("deadline is set");
+        match deadline.as_mut().poll(cx) {
+            Poll::Ready(_) => {
+                debug!("Evicted connection is dropped after grace period");
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rt-tokio"))]
+mod test {
+    use std::future::{pending, Future};
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use futures03::{FutureExt, StreamExt};
+    use futures03::channel::oneshot;
+    use futures03::stream::{iter, once};
+    use tokio1::time::{sleep, timeout};
+
+    use super::Eviction;
+    use crate::std_future::ListenExt;
+
+    type Conn = Pin<Box<dyn Future<Output=()> + Send>>;
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn idle_wakes_listener() {
+        let start = Instant::now();
+        let eviction = Eviction::new(Duration::from_secs(10));
+        let evicted_at = Arc::new(Mutex::new(None));
+        let (tx, rx) = oneshot::channel();
+
+        let evicted = evicted_at.clone();
+        let a: Conn = Box::pin(eviction.wrap(|conn| async move {
+            sleep(Duration::from_millis(50)).await;
+            conn.idle();
+            conn.evicted().await;
+            *evicted.lock().unwrap() = Some(Instant::now());
+        }));
+        // never becomes idle
+        let b: Conn = Box::pin(eviction.wrap(|_| pending()));
+        let c: Conn = Box::pin(async move {
+            tx.send(Instant::now()).ok();
+        });
+        let c = sleep(Duration::from_millis(10)).map(move |_| c);
+        let conns = iter(vec![a, b]).chain(once(c));
+        let mut listen = conns.listen(2);
+        listen.evict_idle(&eviction);
+        tokio1::spawn(listen);
+
+        // only guards the test, listener isn't woken up by this timer
+        let accepted = timeout(Duration::from_secs(1), rx).await
+            .expect("idle connection is evicted").unwrap();
+        let evicted = evicted_at.lock().unwrap().expect("evicted");
+        assert!(evicted < accepted);
+        assert!(accepted - start < Duration::from_millis(500));
+        assert_eq!(eviction.evicted(), 1);
+    }
+}
@@ -0,0 +1,280 @@
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+use futures03::Stream;
+use pin_project_lite::pin_project;
+
+use crate::std_future::cidr::Cidr;
+use crate::peer::PeerAddr;
+
+
+/// Lists of allowed and denied networks for `PeerFilter`
+///
+/// Denied networks take precedence. If allow list is empty, all peers
+/// which aren't denied are accepted, otherwise only peers from allowed
+/// networks are.
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+struct Counters {
+    accepted: AtomicUsize,
+    denied: AtomicUsize,
+}
+
+/// Counters of the `PeerFilter` stage
+///
+/// Returned by `PeerFilter::stats`. It's cheap to clone and can be read
+/// from any thread while the stage is running.
+#[derive(Clone)]
+pub struct FilterStats {
+    counters: Arc<Counters>,
+}
+
+pin_project! {
+    /// A structure returned by `ListenExt::filter_peers`
+    ///
+    /// This is a stream which closes sockets from peers rejected by the
+    /// current `AccessList` and yields other sockets unchanged. Sockets
+    /// which peer address can't be determined are closed too.
+    ///
+    /// Like `BindMany` it receives a stream of lists, every new value
+    /// replaces the previous one (already accepted connections are not
+    /// affected). No connections are accepted until the first list is
+    /// received, and the last list is used forever if the stream ends.
+    pub struct PeerFilter<S, L> {
+        #[pin]
+        stream: S,
+        #[pin]
+        lists: L,
+        lists_done: bool,
+        list: Option<AccessList>,
+        stats: FilterStats,
+    }
+}
+
+impl AccessList {
+    /// Create an empty list which accepts all peers
+    pub fn new() -> AccessList {
+        AccessList::default()
+    }
+
+    /// Adds a network to the allow list
+    pub fn allow(&mut self, network: Cidr) -> &mut Self {
+        self.allow.push(network);
+        self
+    }
+
+    /// Adds a network to the deny list
+    pub fn deny(&mut self, network: Cidr) -> &mut Self {
+        self.deny.push(network);
+        self
+    }
+
+    /// Returns `true` if connections from the address are accepted
+    pub fn is_allowed(&self, addr: &IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(addr)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|n| n.contains(addr))
+    }
+}
+
+pub fn new<S, L>(stream: S, lists: L) -> PeerFilter<S, L>
+    where S: Stream,
+          S::Item: PeerAddr,
+          L: Stream<Item=AccessList>,
+{
+    PeerFilter {
+        stream,
+        lists,
+        lists_done: false,
+        list: None,
+        stats: FilterStats {
+            counters: Arc::new(Counters {
+                accepted: AtomicUsize::new(0),
+                denied: AtomicUsize::new(0),
+            }),
+        },
+    }
+}
+
+impl FilterStats {
+    /// Total number of sockets passed through the filter
+    pub fn accepted(&self) -> usize {
+        self.counters.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Total number of sockets closed by the filter
+    pub fn denied(&self) -> usize {
+        self.counters.denied.load(Ordering::Relaxed)
+    }
+}
+
+impl<S, L> PeerFilter<S, L> {
+    /// Returns a handle to filter counters
+    pub fn stats(&self) -> FilterStats {
+        self.stats.clone()
+    }
+}
+
+impl<S, L> Stream for PeerFilter<S, L>
+    where S: Stream,
+          S::Item: PeerAddr,
+          L: Stream<Item=AccessList>,
+{
+    type Item = S::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Option<Self::Item>>
+    {
+        let mut this = self.project();
+        while !*this.lists_done {
+            match this.lists.as_mut().poll_next(cx) {
+                Poll::Ready(Some(list)) => {
+                    info!("Access list updated");
+                    *this.list = Some(list);
+                }
+                // last list is used forever
+                Poll::Ready(None) => *this.lists_done = true,
+                Poll::Pending => break,
+            }
+        }
+        let list = match *this.list {
+            Some(ref list) => list,
+            None if *this.lists_done => return Poll::Ready(None),
+            None => return Poll::Pending,
+        };
+        let counters = &this.stats.counters;
+        loop {
+            let socket = match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(socket)) => socket,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            match socket.peer_addr() {
+                Ok(addr) if list.is_allowed(&addr.ip()) => {
+                    counters.accepted.fetch_add(1, Ordering::Relaxed);
+                    return Poll::Ready(Some(socket));
+                }
+                Ok(addr) => {
+                    debug!("Connection from {} is denied", addr);
+                }
+                Err(e) => {
+                    debug!("Can't get peer address: {}", e);
+                }
+            }
+            counters.denied.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::task::{Context, Poll};
+
+    use futures03::StreamExt;
+    use futures03::channel::mpsc;
+    use futures03::stream::{empty, iter};
+    use futures03::task::noop_waker;
+
+    use super::AccessList;
+    use crate::peer::PeerAddr;
+    use crate::std_future::ListenExt;
+
+    #[derive(Debug, PartialEq)]
+    struct Socket(Option<SocketAddr>);
+
+    impl PeerAddr for Socket {
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.0.ok_or_else(|| io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    fn socket(addr: &str) -> Socket {
+        Socket(Some(addr.parse().unwrap()))
+    }
+
+    fn list(allow: &[&str], deny: &[&str]) -> AccessList {
+        let mut list = AccessList::new();
+        for net in allow {
+            list.allow(net.parse().unwrap());
+        }
+        for net in deny {
+            list.deny(net.parse().unwrap());
+        }
+        list
+    }
+
+    #[test]
+    fn pending_before_first_list() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let (tx, rx) = mpsc::unbounded();
+        let mut filter = iter(vec![socket("10.0.0.1:1234")]).filter_peers(rx);
+        let stats = filter.stats();
+        assert_eq!(filter.poll_next_unpin(&mut cx), Poll::Pending);
+        assert_eq!(stats.accepted(), 0);
+        assert_eq!(stats.denied(), 0);
+
+        tx.unbounded_send(AccessList::new()).unwrap();
+        assert_eq!(filter.poll_next_unpin(&mut cx),
+                   Poll::Ready(Some(socket("10.0.0.1:1234"))));
+        assert_eq!(stats.accepted(), 1);
+    }
+
+    #[test]
+    fn no_lists() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut filter = iter(vec![socket("10.0.0.1:1234")])
+            .filter_peers(empty());
+        assert_eq!(filter.poll_next_unpin(&mut cx), Poll::Ready(None));
+    }
+
+    #[test]
+    fn update() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let (lists, lists_rx) = mpsc::unbounded();
+        let (sockets, sockets_rx) = mpsc::unbounded();
+        let mut filter = sockets_rx.filter_peers(lists_rx);
+        let stats = filter.stats();
+
+        lists.unbounded_send(list(&[], &["10.0.0.0/8"])).unwrap();
+        sockets.unbounded_send(socket("10.0.0.1:1234")).unwrap();
+        sockets.unbounded_send(socket("192.168.0.1:1234")).unwrap();
+        assert_eq!(filter.poll_next_unpin(&mut cx),
+                   Poll::Ready(Some(socket("192.168.0.1:1234"))));
+        assert_eq!(stats.denied(), 1);
+
+        // new list replaces the old one
+        lists.unbounded_send(list(&["10.0.0.0/8"], &[])).unwrap();
+        sockets.unbounded_send(socket("192.168.0.1:1234")).unwrap();
+        sockets.unbounded_send(socket("10.0.0.1:1234")).unwrap();
+        assert_eq!(filter.poll_next_unpin(&mut cx),
+                   Poll::Ready(Some(socket("10.0.0.1:1234"))));
+        assert_eq!(stats.denied(), 2);
+
+        // sockets without peer address are denied
+        sockets.unbounded_send(Socket(None)).unwrap();
+        assert_eq!(filter.poll_next_unpin(&mut cx), Poll::Pending);
+        assert_eq!(stats.denied(), 3);
+        assert_eq!(stats.accepted(), 2);
+
+        // last list is used after the stream of lists ends
+        drop(lists);
+        sockets.unbounded_send(socket("192.168.0.1:1234")).unwrap();
+        sockets.unbounded_send(socket("10.1.0.1:1234")).unwrap();
+        assert_eq!(filter.poll_next_unpin(&mut cx),
+                   Poll::Ready(Some(socket("10.1.0.1:1234"))));
+        assert_eq!(stats.denied(), 4);
+        assert_eq!(stats.accepted(), 3);
+    }
+}
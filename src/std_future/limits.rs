@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures03::ready;
+use futures03::task::AtomicWaker;
+
+use crate::peer::PeerAddr;
+use crate::std_future::runtime::{Timer, Bind, Listener, LocalAddr};
+
+
+/// A runtime wrapper which limits connections per listening socket
+///
+/// Use it as a runtime of `BindMany`, so that every bound address has
+/// its own limit of simultaneous connections. A listening socket which
+/// reached the limit isn't polled until one of its connections is closed,
+/// other sockets continue to accept connections:
+///
+/// ```rust,ignore
+/// let mut limits = ListenerLimits::new(Tokio);
+/// limits.max_connections(10000);
+/// limits.address_limit(admin_addr, 50);
+/// BindMany::with_runtime(address_stream, limits)
+///     .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+///     .map(|socket| handle(socket))
+///     .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+///     .await;
+/// ```
+///
+/// Accepted sockets are wrapped into `Limited` which frees the slot when
+/// dropped. Note: the limit of `listen` still applies to all connections,
+/// so it should be at least the sum of per-listener limits.
+///
+/// Connections are counted per address, so when an address is removed
+/// and added back, connections accepted by the old socket still count
+/// against the limit of the new one. Clones of the wrapper share the
+/// counters, so a clone can be kept to inspect them with `active`.
+#[derive(Debug, Clone)]
+pub struct ListenerLimits<R> {
+    runtime: R,
+    default: Option<usize>,
+    addresses: HashMap<SocketAddr, usize>,
+    slots: Arc<Mutex<HashMap<SocketAddr, Arc<Slots>>>>,
+}
+
+/// A listening socket created by `ListenerLimits`
+pub struct LimitedListener<L> {
+    listener: L,
+    slots: Arc<Slots>,
+}
+
+/// A socket accepted by `ListenerLimits` or `Reserve`
+///
+/// Slot of the listening socket (or of the `Reserve` stage) is freed
+/// when this object is dropped.
+#[derive(Debug)]
+pub struct Limited<T> {
+    socket: T,
+    slots: Arc<Slots>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Slots {
+    max: AtomicUsize,
+    active: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+impl Slots {
+    pub(crate) fn new(max: usize) -> Arc<Slots> {
+        Arc::new(Slots {
+            max: AtomicUsize::new(max),
+            active: AtomicUsize::new(0),
+            waker: AtomicWaker::new(),
+        })
+    }
+
+    pub(crate) fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    fn max(&self) -> usize {
+        self.max.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if there is a free slot, otherwise registers task
+    /// to be woken up when slot is freed
+    pub(crate) fn poll_free(&self, cx: &mut Context<'_>) -> bool {
+        if self.active() < self.max() {
+            return true;
+        }
+        self.waker.register(cx.waker());
+        // a connection might have been closed before registering
+        self.active() < self.max()
+    }
+
+    /// Occupies a slot for the socket
+    pub(crate) fn take<T>(self: &Arc<Self>, socket: T) -> Limited<T> {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Limited {
+            socket,
+            slots: self.clone(),
+        }
+    }
+}
+
+impl<R> ListenerLimits<R> {
+    /// Wraps a runtime, no limits are set by default
+    pub fn new(runtime: R) -> ListenerLimits<R> {
+        ListenerLimits {
+            runtime,
+            default: None,
+            addresses: HashMap::new(),
+            slots: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets a limit of connections for every listening socket
+    pub fn max_connections(&mut self, max: usize) -> &mut Self {
+        self.default = Some(max);
+        self
+    }
+
+    /// Sets a limit of connections for the socket bound to the address
+    ///
+    /// This overrides `max_connections`.
+    pub fn address_limit(&mut self, addr: SocketAddr, max: usize)
+        -> &mut Self
+    {
+        self.addresses.insert(addr, max);
+        self
+    }
+
+    /// Returns number of connections accepted on the address and not
+    /// closed yet
+    pub fn active(&self, addr: &SocketAddr) -> usize {
+        self.slots.lock().expect("limits are not poisoned")
+            .get(addr).map(|slots| slots.active()).unwrap_or(0)
+    }
+}
+
+impl<R: Timer> Timer for ListenerLimits<R> {
+    type Sleep = R::Sleep;
+    fn sleep(&self, duration: Duration) -> R::Sleep {
+        self.runtime.sleep(duration)
+    }
+}
+
+impl<R: Bind> Bind for ListenerLimits<R> {
+    type Listener = LimitedListener<R::Listener>;
+    fn bind(&self, addr: &SocketAddr)
+        -> io::Result<LimitedListener<R::Listener>>
+    {
+        let max = self.addresses.get(addr).cloned().or(self.default)
+            .unwrap_or(usize::MAX);
+        let listener = self.runtime.bind(addr)?;
+        let slots = self.slots.lock().expect("limits are not poisoned")
+            .entry(*addr)
+            .and_modify(|slots| slots.max.store(max, Ordering::SeqCst))
+            .or_insert_with(|| Slots::new(max))
+            .clone();
+        Ok(LimitedListener { listener, slots })
+    }
+}
+
+impl<L: Listener> Listener for LimitedListener<L> {
+    type Socket = Limited<L::Socket>;
+    fn poll_accept(&mut self, cx: &mut Context<'_>)
+        -> Poll<io::Result<Limited<L::Socket>>>
+    {
+        if !self.slots.poll_free(cx) {
+            return Poll::Pending;
+        }
+        let socket = ready!(self.listener.poll_accept(cx))?;
+        Poll::Ready(Ok(self.slots.take(socket)))
+    }
+}
+
+impl<T> Limited<T> {
+    /// Returns a reference to the underlying socket
+    pub fn get_ref(&self) -> &T {
+        &self.socket
+    }
+
+    /// Returns a mutable reference to the underlying socket
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.socket
+    }
+}
+
+impl<T> Drop for Limited<T> {
+    fn drop(&mut self) {
+        let active = self.slots.active.fetch_sub(1, Ordering::SeqCst);
+        if active >= self.slots.max() {
+            self.slots.waker.wake();
+        }
+    }
+}
+
+impl<T: PeerAddr> PeerAddr for Limited<T> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+}
+
+impl<T: LocalAddr> LocalAddr for Limited<T> {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+#[cfg(feature = "rt-tokio")]
+mod tokio_impl {
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio1::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::Limited;
+
+    impl<T: AsyncRead + Unpin> AsyncRead for Limited<T> {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>)
+            -> Poll<io::Result<()>>
+        {
+            Pin::new(&mut self.get_mut().socket).poll_read(cx, buf)
+        }
+    }
+
+    impl<T: AsyncWrite + Unpin> AsyncWrite for Limited<T> {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>,
+            buf: &[u8])
+            -> Poll<io::Result<usize>>
+        {
+            Pin::new(&mut self.get_mut().socket).poll_write(cx, buf)
+        }
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>)
+            -> Poll<io::Result<()>>
+        {
+            Pin::new(&mut self.get_mut().socket).poll_flush(cx)
+        }
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>)
+            -> Poll<io::Result<()>>
+        {
+            Pin::new(&mut self.get_mut().socket).poll_shutdown(cx)
+        }
+    }
+}
+
+// `futures::io` traits are used by smol and async-std
+impl<T: futures03::io::AsyncRead + Unpin> futures03::io::AsyncRead
+    for Limited<T>
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>,
+        buf: &mut [u8])
+        -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_read(cx, buf)
+    }
+}
+
+impl<T: futures03::io::AsyncWrite + Unpin> futures03::io::AsyncWrite
+    for Limited<T>
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+        -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::task::{Context, Poll};
+
+    use futures03::task::noop_waker;
+
+    use super::ListenerLimits;
+    use crate::std_future::runtime::{Bind, Listener};
+
+    /// A runtime which accepts a connection on every poll
+    #[derive(Debug, Clone)]
+    struct Always;
+
+    impl Bind for Always {
+        type Listener = Always;
+        fn bind(&self, _: &SocketAddr) -> io::Result<Always> {
+            Ok(Always)
+        }
+    }
+
+    impl Listener for Always {
+        type Socket = ();
+        fn poll_accept(&mut self, _: &mut Context<'_>)
+            -> Poll<io::Result<()>>
+        {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn rebind_keeps_count() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:8081".parse().unwrap();
+        let mut limits = ListenerLimits::new(Always);
+        limits.max_connections(2);
+        let mut listener = limits.bind(&addr).unwrap();
+        let first = listener.poll_accept(&mut cx);
+        let second = listener.poll_accept(&mut cx);
+        assert!(matches!(first, Poll::Ready(Ok(_))));
+        assert!(matches!(second, Poll::Ready(Ok(_))));
+        assert!(listener.poll_accept(&mut cx).is_pending());
+        assert_eq!(limits.active(&addr), 2);
+        drop(listener);
+
+        let mut listener = limits.bind(&addr).unwrap();
+        assert!(listener.poll_accept(&mut cx).is_pending());
+        drop(first);
+        assert_eq!(limits.active(&addr), 1);
+        let third = listener.poll_accept(&mut cx);
+        assert!(third.is_ready());
+        assert_eq!(limits.active(&addr), 2);
+
+        let mut listener = limits.bind(&other).unwrap();
+        let fourth = listener.poll_accept(&mut cx);
+        assert!(fourth.is_ready());
+        assert_eq!(limits.active(&other), 1);
+        assert_eq!(limits.active(&addr), 2);
+    }
+}
@@ -0,0 +1,196 @@
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures03::{Stream, StreamExt, FutureExt};
+use futures03::future::CatchUnwind;
+use futures03::stream::FuturesUnordered;
+use pin_project_lite::pin_project;
+
+use crate::std_future::evict::{Eviction, Tracker};
+use crate::std_future::runtime::Timer;
+use crate::util::panic_message;
+
+
+pin_project! {
+    /// A structure returned by `ListenExt::listen`
+    ///
+    /// This is a future that returns when incoming stream has been closed
+    /// and all connections (futures) have been processed. A panic in
+    /// connection future is caught and logged, and only this connection
+    /// is dropped.
+    ///
+    /// With `evict_idle` one more connection is taken from the stream
+    /// when limit is reached. It's held until an idle connection is
+    /// evicted to make room for it.
+    pub struct Listen<S>
+        where S: Stream,
+              S::Item: Future<Output=()>,
+    {
+        #[pin]
+        stream: S,
+        done: bool,
+        futures: FuturesUnordered<CatchUnwind<AssertUnwindSafe<S::Item>>>,
+        max_connections: usize,
+        eviction: Option<Arc<Tracker>>,
+        held: Option<S::Item>,
+    }
+}
+
+pub fn new<S: Stream>(stream: S, limit: usize) -> Listen<S>
+    where S::Item: Future<Output=()>,
+{
+    Listen {
+        stream,
+        done: false,
+        futures: FuturesUnordered::new(),
+        max_connections: limit,
+        eviction: None,
+        held: None,
+    }
+}
+
+impl<S: Stream> Listen<S>
+    where S::Item: Future<Output=()>,
+{
+    /// Returns number of connections that are currently running
+    pub fn active(&self) -> usize {
+        self.futures.len()
+    }
+
+    /// Evicts idle connections when limit is reached and there is a new
+    /// connection to accept
+    ///
+    /// Only connections wrapped by `Eviction::wrap` can be evicted. See
+    /// `Eviction` for more info.
+    pub fn evict_idle<T>(&mut self, eviction: &Eviction<T>) -> &mut Self
+        where T: Timer + Clone,
+    {
+        self.eviction = Some(eviction.tracker().clone());
+        self
+    }
+}
+
+impl<S: Stream> Future for Listen<S>
+    where S::Item: Future<Output=()>,
+{
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut this = self.project();
+        loop {
+            if this.futures.len() < *this.max_connections {
+                if let Some(f) = this.held.take() {
+                    this.futures.push(AssertUnwindSafe(f).catch_unwind());
+                    if let Some(ref eviction) = *this.eviction {
+                        eviction.stop_waiting();
+                    }
+                }
+            }
+            while !*this.done && this.futures.len() < *this.max_connections {
+                match this.stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(f)) => {
+                        this.futures.push(
+                            AssertUnwindSafe(f).catch_unwind());
+                    }
+                    Poll::Ready(None) => *this.done = true,
+                    Poll::Pending => break,
+                }
+            }
+            if let Some(ref eviction) = *this.eviction {
+                if this.held.is_none() && !*this.done &&
+                    this.futures.len() >= *this.max_connections
+                {
+                    match this.stream.as_mut().poll_next(cx) {
+                        Poll::Ready(Some(f)) => *this.held = Some(f),
+                        Poll::Ready(None) => *this.done = true,
+                        Poll::Pending => {}
+                    }
+                }
+                if this.held.is_some() {
+                    eviction.evict_idle(cx);
+                }
+            }
+            match this.futures.poll_next_unpin(cx) {
+                // Some future just finished, let's check for next one
+                Poll::Ready(Some(Ok(()))) => continue,
+                Poll::Ready(Some(Err(payload))) => {
+                    error!("Connection panicked: {}",
+                        panic_message(&*payload));
+                    continue;
+                }
+                // Stream is done
+                Poll::Ready(None) if *this.done && this.held.is_none() => {
+                    return Poll::Ready(());
+                }
+                // No future ready
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rt-tokio"))]
+mod test {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures03::stream::iter;
+    use tokio1::time::sleep;
+
+    use crate::std_future::ListenExt;
+
+    type Conn = Pin<Box<dyn Future<Output=()> + Send>>;
+
+    #[derive(Clone, Default)]
+    struct Counter {
+        running: Arc<AtomicUsize>,
+        max: Arc<AtomicUsize>,
+        finished: Arc<AtomicUsize>,
+    }
+
+    impl Counter {
+        fn conn(&self) -> Conn {
+            let counter = self.clone();
+            Box::pin(async move {
+                let running = counter.running.fetch_add(1, Ordering::SeqCst);
+                counter.max.fetch_max(running + 1, Ordering::SeqCst);
+                sleep(Duration::from_millis(5)).await;
+                counter.running.fetch_sub(1, Ordering::SeqCst);
+                counter.finished.fetch_add(1, Ordering::SeqCst);
+            })
+        }
+    }
+
+    fn panicking() -> Conn {
+        Box::pin(async { panic!("connection panicked") })
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn limit() {
+        let counter = Counter::default();
+        let conns = iter((0..6).map(|_| counter.conn()).collect::<Vec<_>>());
+        conns.listen(2).await;
+        assert_eq!(counter.max.load(Ordering::SeqCst), 2);
+        assert_eq!(counter.running.load(Ordering::SeqCst), 0);
+        assert_eq!(counter.finished.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn panic_isolated() {
+        let counter = Counter::default();
+        let conns = vec![
+            panicking(),
+            counter.conn(),
+            panicking(),
+            counter.conn(),
+        ];
+        // a panicked connection releases its slot
+        iter(conns).listen(1).await;
+        assert_eq!(counter.finished.load(Ordering::SeqCst), 2);
+    }
+}
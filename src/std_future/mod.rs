@@ -0,0 +1,109 @@
+//! Listener combinators for `std::future` and tokio 1.x
+//!
+//! These are counterparts of `BindMany`, `SleepOnError` and `Listen` from
+//! the crate root (which are for futures 0.1 and tokio 0.1 and are only
+//! available with the `legacy` feature, enabled by default). Semantics are
+//! the same, except that connection futures have `Output = ()`, i.e. they
+//! must handle (log) errors by themselves:
+//!
+//! ```rust,ignore
+//!   BindMany::new(address_stream)
+//!   .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+//!   .map(|socket| async move {
+//!       if let Err(e) = Proto::new(socket).await {
+//!           error!("Conn error: {}", e);
+//!       }
+//!   })
+//!   .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+//!   .await;
+//! ```
+//!
+//! Only the combinators described in this module are available for
+//! `std::future`. The following features of the crate root are not ported
+//! yet, and there are no equivalents here:
+//!
+//! * `Stats`, `Limit` and `SharedLimit` (connection limits that can be
+//!   inspected or changed at runtime and shared between listeners)
+//! * error policies (`listen_with_errors`, `ErrorHandler`) and connection
+//!   timeouts (`max_age`, `idle_timeout`) of `Listen`
+//! * graceful shutdown (`Shutdown`, `WithShutdown`) and `Saturation`
+//! * `per_ip_limit`, `rate_limit`, `first_byte_timeout` and `overload`
+//! * spawning connections as separate tasks (`spawn_connections`)
+//!
+//! Panics of connection futures are caught and logged, like in the crate
+//! root.
+//!
+//! The limit of `Listen` is fixed, use `ListenerLimits` or `Reserve` for
+//! finer-grained limits, and handle errors and timeouts in the connection
+//! future itself.
+//!
+//! Timers and listening sockets are created by a runtime, which is tokio
+//! by default (`rt-tokio` feature). With `rt-async-io` feature `AsyncIo`
+//! runtime can be used for smol and async-std:
+//!
+//! ```rust,ignore
+//!   BindMany::with_runtime(address_stream, AsyncIo)
+//!   .sleep_on_error_with(AsyncIo, TIME_TO_WAIT_ON_ERROR)
+//!   .map(|socket| handle(socket))
+//!   .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+//!   .await;
+//! ```
+//!
+//! Other runtimes can be supported by implementing `Timer` and `Bind`
+//! traits. `ListenerLimits` wraps a runtime to set separate connection
+//! limits for every bound address, and `ListenExt::reserve` keeps
+//! connection slots for trusted clients. Idle keep-alive connections can
+//! be evicted to make room for new ones, see `Eviction`.
+//!
+//! With `rustls` feature, TLS handshake can be made in a separate stage
+//! with its own concurrency limit, see `ListenExt::tls_handshake`.
+//! Certificates can be reloaded using `ListenExt::tls_handshake_reload`.
+//!
+//! When running behind a load balancer, use `ListenExt::proxy_protocol`
+//! to receive real client addresses via the PROXY protocol. Peers can be
+//! allowed or denied by network using `ListenExt::filter_peers`, and
+//! abusive peers can be banned temporarily with `ListenExt::ban_peers`.
+//! Different protocols (e.g. TLS and plain HTTP) can be served on the same
+//! port with `ListenExt::sniff`.
+
+mod ban;
+mod bind;
+mod cidr;
+mod evict;
+mod filter;
+mod limits;
+mod listen;
+#[cfg(feature = "rt-tokio")]
+mod proxy;
+mod reserve;
+mod runtime;
+mod sleep_on_error;
+#[cfg(feature = "rt-tokio")]
+mod sniff;
+#[cfg(feature = "rustls")]
+mod tls;
+mod traits;
+
+pub use self::ban::{BanTable, BanPeers};
+pub use self::bind::BindMany;
+pub use self::cidr::{Cidr, ParseCidrError};
+pub use self::evict::{Eviction, IdleHandle, Evicted, Evictable};
+pub use self::filter::{PeerFilter, AccessList, FilterStats};
+pub use self::limits::{ListenerLimits, LimitedListener, Limited};
+pub use self::listen::Listen;
+#[cfg(feature = "rt-tokio")]
+pub use self::proxy::{ProxyProtocol, Proxied};
+pub use self::reserve::{Reserve, ReserveStats};
+pub use self::runtime::{Timer, Bind, Listener, LocalAddr, PeerAddr};
+#[cfg(feature = "rt-tokio")]
+pub use self::runtime::Tokio;
+#[cfg(feature = "rt-async-io")]
+pub use self::runtime::AsyncIo;
+pub use self::sleep_on_error::SleepOnError;
+#[cfg(feature = "rt-tokio")]
+pub use self::sniff::{Sniff, Sniffed, Detector, Detection};
+#[cfg(feature = "rt-tokio")]
+pub use self::sniff::{TlsClientHello, Http1, Http2Preface, ProxyHeader};
+pub use self::traits::ListenExt;
+#[cfg(feature = "rustls")]
+pub use self::tls::{TlsHandshake, TlsConfig, HandshakeStats};
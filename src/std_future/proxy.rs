@@ -0,0 +1,612 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures03::{Stream, StreamExt, ready};
+use futures03::stream::FuturesUnordered;
+use pin_project_lite::pin_project;
+use tokio1::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio1::time::{sleep, Sleep};
+
+use crate::std_future::cidr::Cidr;
+use crate::peer::PeerAddr;
+use crate::std_future::runtime::LocalAddr;
+
+
+pub(crate) const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+pub(crate) const V1_PREFIX: &[u8; 6] = b"PROXY ";
+/// Minimal size of both headers
+const MIN_HEADER: usize = 15;
+/// Maximum length of the v1 header as defined in the spec
+const MAX_V1_HEADER: usize = 107;
+
+pin_project! {
+    /// A structure returned by `ListenExt::proxy_protocol`
+    ///
+    /// This is a stream which reads PROXY protocol header (both text v1
+    /// and binary v2) from accepted sockets and yields `Proxied` sockets
+    /// which know the real addresses of the client. At most `max_pending`
+    /// (default is `100`) headers are read at once, when there are more,
+    /// the underlying stream isn't polled.
+    ///
+    /// Connections with invalid header, header larger than
+    /// `max_header_size` (default is `1024` bytes, TLVs of v2 header are
+    /// counted too) or not received within a timeout are closed.
+    pub struct ProxyProtocol<S>
+        where S: Stream,
+    {
+        #[pin]
+        stream: S,
+        done: bool,
+        timeout: Duration,
+        max_header_size: usize,
+        max_pending: usize,
+        trusted: Option<Vec<Cidr>>,
+        pending: FuturesUnordered<ReadHeader<S::Item>>,
+    }
+}
+
+/// A socket with addresses decoded from the PROXY protocol header
+///
+/// Implements `AsyncRead` and `AsyncWrite` so it can be used in place of
+/// the original socket. `PeerAddr` returns the original client address.
+///
+/// Header is read in chunks, so some data sent by the client after the
+/// header might be read too. Such data is returned by `AsyncRead` first.
+#[derive(Debug)]
+pub struct Proxied<T> {
+    socket: T,
+    source: Option<SocketAddr>,
+    destination: Option<SocketAddr>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Header {
+    /// At least this number of bytes is needed
+    Incomplete(usize),
+    /// Addresses and the length of the header
+    Complete(Option<(SocketAddr, SocketAddr)>, usize),
+}
+
+pin_project! {
+    struct ReadHeader<T> {
+        socket: Option<T>,
+        buf: Vec<u8>,
+        max_size: usize,
+        #[pin]
+        timer: Sleep,
+    }
+}
+
+pub fn new<S: Stream>(stream: S, timeout: Duration) -> ProxyProtocol<S>
+    where S::Item: AsyncRead + PeerAddr + Unpin,
+{
+    ProxyProtocol {
+        stream,
+        done: false,
+        timeout,
+        max_header_size: 1024,
+        max_pending: 100,
+        trusted: None,
+        pending: FuturesUnordered::new(),
+    }
+}
+
+impl<S: Stream> ProxyProtocol<S> {
+    /// Sets maximum size of the header (default is `1024` bytes)
+    ///
+    /// Note: v1 header is never larger than 107 bytes.
+    pub fn max_header_size(&mut self, size: usize) -> &mut Self {
+        self.max_header_size = size;
+        self
+    }
+
+    /// Sets maximum number of headers being read at once (default `100`)
+    pub fn max_pending(&mut self, max_pending: usize) -> &mut Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Accept headers only from proxies in these networks
+    ///
+    /// Connections from other peers are yielded immediately, with no
+    /// header read, so clients connecting directly can't spoof their
+    /// address. By default header is required on every connection.
+    pub fn trusted_proxies<I>(&mut self, networks: I) -> &mut Self
+        where I: IntoIterator<Item=Cidr>,
+    {
+        self.trusted = Some(networks.into_iter().collect());
+        self
+    }
+
+    /// Returns number of headers currently being read
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl<T> Proxied<T> {
+    /// Returns source (client) address from the header
+    ///
+    /// This is `None` for health checks of the proxy (`LOCAL` command
+    /// or `UNKNOWN` protocol), for unsupported address families and for
+    /// connections from untrusted peers (see
+    /// `ProxyProtocol::trusted_proxies`).
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.source
+    }
+
+    /// Returns destination address from the header
+    ///
+    /// This is the address client has connected to at the proxy.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.destination
+    }
+
+    /// Returns a reference to the underlying socket
+    pub fn get_ref(&self) -> &T {
+        &self.socket
+    }
+
+    /// Returns a mutable reference to the underlying socket
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.socket
+    }
+
+    /// Returns the underlying socket and data received after the header
+    /// which hasn't been read yet
+    pub fn into_parts(mut self) -> (T, Vec<u8>) {
+        self.buf.drain(..self.pos);
+        (self.socket, self.buf)
+    }
+}
+
+impl<T: PeerAddr> PeerAddr for Proxied<T> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self.source {
+            Some(addr) => Ok(addr),
+            None => self.socket.peer_addr(),
+        }
+    }
+}
+
+impl<T: LocalAddr> LocalAddr for Proxied<T> {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Proxied<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>)
+        -> Poll<io::Result<()>>
+    {
+        let this = self.get_mut();
+        if this.pos < this.buf.len() {
+            let n = buf.remaining().min(this.buf.len() - this.pos);
+            buf.put_slice(&this.buf[this.pos..this.pos + n]);
+            this.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.socket).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Proxied<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+        -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_flush(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_shutdown(cx)
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn parse(buf: &[u8], max_size: usize) -> io::Result<Header> {
+    if buf.is_empty() {
+        return Ok(Header::Incomplete(MIN_HEADER));
+    }
+    if buf[0] == V2_SIGNATURE[0] {
+        let sig = buf.len().min(V2_SIGNATURE.len());
+        if buf[..sig] != V2_SIGNATURE[..sig] {
+            return Err(invalid("no PROXY protocol header"));
+        }
+        if buf.len() < 16 {
+            return Ok(Header::Incomplete(16 - buf.len()));
+        }
+        let total = 16 + usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+        if total > max_size {
+            return Err(invalid("PROXY protocol header is too large"));
+        }
+        if buf.len() < total {
+            return Ok(Header::Incomplete(total - buf.len()));
+        }
+        parse_v2(&buf[..total]).map(|addrs| Header::Complete(addrs, total))
+    } else {
+        let prefix = buf.len().min(V1_PREFIX.len());
+        if buf[..prefix] != V1_PREFIX[..prefix] {
+            return Err(invalid("no PROXY protocol header"));
+        }
+        let limit = MAX_V1_HEADER.min(max_size);
+        let line = &buf[..buf.len().min(limit)];
+        if let Some(end) = line.windows(2).position(|w| w == b"\r\n") {
+            let total = end + 2;
+            return parse_v1(&buf[..total])
+                .map(|addrs| Header::Complete(addrs, total));
+        }
+        if buf.len() >= limit {
+            return Err(invalid("PROXY protocol header is too large"));
+        }
+        Ok(Header::Incomplete(MIN_HEADER.saturating_sub(buf.len()).max(1)))
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    let line = str::from_utf8(&buf[..buf.len()-2])
+        .map_err(|_| invalid("invalid PROXY v1 header"))?;
+    let mut parts = line.split(' ').skip(1);
+    let ipv6 = match parts.next() {
+        Some("UNKNOWN") => return Ok(None),
+        Some("TCP4") => false,
+        Some("TCP6") => true,
+        _ => return Err(invalid("invalid PROXY v1 protocol")),
+    };
+    let mut next = || parts.next().ok_or(invalid("invalid PROXY v1 header"));
+    let (src, dst) = (next()?, next()?);
+    let (src_port, dst_port) = (next()?, next()?);
+    let src: IpAddr = src.parse()
+        .map_err(|_| invalid("invalid PROXY v1 address"))?;
+    let dst: IpAddr = dst.parse()
+        .map_err(|_| invalid("invalid PROXY v1 address"))?;
+    let src_port: u16 = src_port.parse()
+        .map_err(|_| invalid("invalid PROXY v1 port"))?;
+    let dst_port: u16 = dst_port.parse()
+        .map_err(|_| invalid("invalid PROXY v1 port"))?;
+    if next().is_ok() {
+        return Err(invalid("invalid PROXY v1 header"));
+    }
+    if src.is_ipv6() != ipv6 || dst.is_ipv6() != ipv6 {
+        return Err(invalid("PROXY v1 address doesn't match protocol"));
+    }
+    Ok(Some((SocketAddr::new(src, src_port), SocketAddr::new(dst, dst_port))))
+}
+
+fn parse_v2(buf: &[u8]) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    if buf[12] >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    match buf[12] & 0x0F {
+        0 => return Ok(None),  // LOCAL, e.g. health check of the proxy
+        1 => {}
+        _ => return Err(invalid("invalid PROXY v2 command")),
+    }
+    let data = &buf[16..];
+    let port = |idx: usize| u16::from_be_bytes([data[idx], data[idx+1]]);
+    match buf[13] >> 4 {
+        1 if data.len() >= 12 => {
+            let mut src = [0u8; 4];
+            let mut dst = [0u8; 4];
+            src.copy_from_slice(&data[..4]);
+            dst.copy_from_slice(&data[4..8]);
+            Ok(Some((
+                SocketAddr::new(Ipv4Addr::from(src).into(), port(8)),
+                SocketAddr::new(Ipv4Addr::from(dst).into(), port(10)),
+            )))
+        }
+        2 if data.len() >= 36 => {
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&data[..16]);
+            dst.copy_from_slice(&data[16..32]);
+            Ok(Some((
+                SocketAddr::new(Ipv6Addr::from(src).into(), port(32)),
+                SocketAddr::new(Ipv6Addr::from(dst).into(), port(34)),
+            )))
+        }
+        1 | 2 => Err(invalid("PROXY v2 address block is too short")),
+        // unix sockets and unspecified family
+        _ => Ok(None),
+    }
+}
+
+impl<T: AsyncRead + Unpin> Future for ReadHeader<T> {
+    type Output = io::Result<Proxied<T>>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Self::Output>
+    {
+        let this = self.project();
+        if this.timer.poll(cx).is_ready() {
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut,
+                "timed out reading PROXY protocol header")));
+        }
+        loop {
+            let want = match parse(this.buf, *this.max_size)? {
+                Header::Complete(addrs, len) => {
+                    let socket = this.socket.take()
+                        .expect("future is not polled after completion");
+                    let mut buf = std::mem::take(this.buf);
+                    buf.drain(..len);
+                    return Poll::Ready(Ok(Proxied {
+                        socket,
+                        source: addrs.map(|(src, _)| src),
+                        destination: addrs.map(|(_, dst)| dst),
+                        buf,
+                        pos: 0,
+                    }));
+                }
+                Header::Incomplete(want) => want,
+            };
+            let socket = this.socket.as_mut()
+                .expect("future is not polled after completion");
+            // read as much as fits, the rest is returned by `Proxied`
+            let len = this.buf.len();
+            let want = want.max(this.max_size.saturating_sub(len));
+            this.buf.resize(len + want, 0);
+            let mut read_buf = ReadBuf::new(&mut this.buf[len..]);
+            let result = Pin::new(socket).poll_read(cx, &mut read_buf);
+            let filled = read_buf.filled().len();
+            this.buf.truncate(len + filled);
+            ready!(result)?;
+            if filled == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+    }
+}
+
+impl<S: Stream> Stream for ProxyProtocol<S>
+    where S::Item: AsyncRead + PeerAddr + Unpin,
+{
+    type Item = Proxied<S::Item>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Option<Self::Item>>
+    {
+        let mut this = self.project();
+        loop {
+            while !*this.done && this.pending.len() < *this.max_pending {
+                match this.stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(socket)) => {
+                        if let Some(ref trusted) = *this.trusted {
+                            let peer = socket.peer_addr().ok();
+                            let is_trusted = peer.is_some_and(|peer| {
+                                trusted.iter().any(|n| n.contains(&peer.ip()))
+                            });
+                            if !is_trusted {
+                                return Poll::Ready(Some(Proxied {
+                                    socket,
+                                    source: None,
+                                    destination: None,
+                                    buf: Vec::new(),
+                                    pos: 0,
+                                }));
+                            }
+                        }
+                        this.pending.push(ReadHeader {
+                            socket: Some(socket),
+                            buf: Vec::with_capacity(MIN_HEADER),
+                            max_size: *this.max_header_size,
+                            timer: sleep(*this.timeout),
+                        });
+                    }
+                    Poll::Ready(None) => *this.done = true,
+                    Poll::Pending => break,
+                }
+            }
+            match this.pending.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(socket))) => {
+                    return Poll::Ready(Some(socket));
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    debug!("Error reading PROXY protocol header: {}", e);
+                }
+                Poll::Ready(None) if *this.done => return Poll::Ready(None),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use tokio1::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio1::time::sleep;
+
+    use super::{parse, Header, ReadHeader, V2_SIGNATURE, MAX_V1_HEADER};
+
+    fn addrs(src: &str, dst: &str) -> Option<(SocketAddr, SocketAddr)> {
+        Some((src.parse().unwrap(), dst.parse().unwrap()))
+    }
+
+    fn v2(command: u8, family: u8, data: &[u8]) -> Vec<u8> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.push(0x20 | command);
+        buf.push(family << 4 | 1);
+        buf.extend(&(data.len() as u16).to_be_bytes());
+        buf.extend(data);
+        buf
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let buf = b"PROXY TCP4 1.2.3.4 5.6.7.8 1234 80\r\nGET /";
+        assert_eq!(parse(buf, 1024).unwrap(), Header::Complete(
+            addrs("1.2.3.4:1234", "5.6.7.8:80"), buf.len() - 5));
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let buf = b"PROXY TCP6 ::1 2001:db8::1 1234 443\r\n";
+        assert_eq!(parse(buf, 1024).unwrap(), Header::Complete(
+            addrs("[::1]:1234", "[2001:db8::1]:443"), buf.len()));
+    }
+
+    #[test]
+    fn v1_unknown() {
+        let buf = b"PROXY UNKNOWN\r\n";
+        assert_eq!(parse(buf, 1024).unwrap(),
+            Header::Complete(None, buf.len()));
+        let buf = b"PROXY UNKNOWN ::1 ::2 1 2\r\n";
+        assert_eq!(parse(buf, 1024).unwrap(),
+            Header::Complete(None, buf.len()));
+    }
+
+    #[test]
+    fn v1_family_mismatch() {
+        assert!(parse(b"PROXY TCP4 ::1 ::2 1 2\r\n", 1024).is_err());
+        assert!(parse(b"PROXY TCP4 1.2.3.4 ::2 1 2\r\n", 1024).is_err());
+        assert!(parse(b"PROXY TCP6 1.2.3.4 5.6.7.8 1 2\r\n", 1024).is_err());
+    }
+
+    #[test]
+    fn v1_invalid() {
+        assert!(parse(b"PROXY TCP5 1.2.3.4 5.6.7.8 1 2\r\n", 1024).is_err());
+        assert!(parse(b"PROXY TCP4 1.2.3.4 5.6.7.8 1\r\n", 1024).is_err());
+        assert!(parse(b"PROXY TCP4 1.2.3.4 5.6.7.8 1 2 3\r\n", 1024)
+            .is_err());
+        assert!(parse(b"PROXY TCP4 1.2.3.4 5.6.7.8 1 65536\r\n", 1024)
+            .is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n", 1024).is_err());
+    }
+
+    #[test]
+    fn v1_truncated() {
+        assert_eq!(parse(b"", 1024).unwrap(), Header::Incomplete(15));
+        assert_eq!(parse(b"PROX", 1024).unwrap(), Header::Incomplete(11));
+        assert_eq!(parse(b"PROXY TCP4 1.2.3.4", 1024).unwrap(),
+            Header::Incomplete(1));
+        assert_eq!(parse(b"PROXY UNKNOWN\r", 1024).unwrap(),
+            Header::Incomplete(1));
+    }
+
+    #[test]
+    fn v1_too_large() {
+        let mut buf = b"PROXY UNKNOWN".to_vec();
+        buf.resize(MAX_V1_HEADER, b' ');
+        assert!(parse(&buf, 1024).is_err());
+        assert!(parse(&buf[..50], 50).is_err());
+        // CRLF beyond the limit doesn't count
+        buf.extend(b"\r\n");
+        assert!(parse(&buf, 1024).is_err());
+        assert_eq!(parse(&buf[..50], 1024).unwrap(), Header::Incomplete(1));
+    }
+
+    #[test]
+    fn v2_ipv4() {
+        let mut data = vec![1, 2, 3, 4, 5, 6, 7, 8, 0x04, 0xD2, 0, 80];
+        let buf = v2(1, 1, &data);
+        assert_eq!(parse(&buf, 1024).unwrap(), Header::Complete(
+            addrs("1.2.3.4:1234", "5.6.7.8:80"), 28));
+        // TLVs are skipped, data after the header is kept
+        data.extend(&[0x04, 0, 1, 0xFF]);
+        let mut buf = v2(1, 1, &data);
+        buf.extend(b"GET /");
+        assert_eq!(parse(&buf, 1024).unwrap(), Header::Complete(
+            addrs("1.2.3.4:1234", "5.6.7.8:80"), 32));
+    }
+
+    #[test]
+    fn v2_ipv6() {
+        let mut data = vec![0; 36];
+        data[15] = 1;
+        data[16] = 0x20;
+        data[17] = 0x01;
+        data[31] = 2;
+        data[32..].copy_from_slice(&[0x04, 0xD2, 0x01, 0xBB]);
+        assert_eq!(parse(&v2(1, 2, &data), 1024).unwrap(), Header::Complete(
+            addrs("[::1]:1234", "[2001::2]:443"), 52));
+    }
+
+    #[test]
+    fn v2_local() {
+        let buf = v2(0, 1, &[0; 12]);
+        assert_eq!(parse(&buf, 1024).unwrap(), Header::Complete(None, 28));
+        let buf = v2(0, 0, &[]);
+        assert_eq!(parse(&buf, 1024).unwrap(), Header::Complete(None, 16));
+    }
+
+    #[test]
+    fn v2_unspec_and_unix() {
+        let buf = v2(1, 0, &[]);
+        assert_eq!(parse(&buf, 1024).unwrap(), Header::Complete(None, 16));
+        let buf = v2(1, 3, &[0; 216]);
+        assert_eq!(parse(&buf, 1024).unwrap(), Header::Complete(None, 232));
+    }
+
+    #[test]
+    fn v2_family_mismatch() {
+        // IPv6 family with the address block of IPv4
+        assert!(parse(&v2(1, 2, &[0; 12]), 1024).is_err());
+        assert!(parse(&v2(1, 1, &[0; 8]), 1024).is_err());
+    }
+
+    #[test]
+    fn v2_invalid() {
+        let mut buf = v2(1, 1, &[0; 12]);
+        buf[12] = 0x11;  // version 1
+        assert!(parse(&buf, 1024).is_err());
+        buf[12] = 0x22;  // unknown command
+        assert!(parse(&buf, 1024).is_err());
+        let mut buf = v2(1, 1, &[0; 12]);
+        buf[5] = b'X';
+        assert!(parse(&buf, 1024).is_err());
+    }
+
+    #[test]
+    fn v2_truncated() {
+        let buf = v2(1, 1, &[0; 12]);
+        assert_eq!(parse(&buf[..1], 1024).unwrap(), Header::Incomplete(15));
+        assert_eq!(parse(&buf[..15], 1024).unwrap(), Header::Incomplete(1));
+        assert_eq!(parse(&buf[..20], 1024).unwrap(), Header::Incomplete(8));
+    }
+
+    #[test]
+    fn v2_too_large() {
+        let buf = v2(1, 1, &[0; 100]);
+        assert!(parse(&buf[..16], 100).is_err());
+        assert!(parse(&buf, 116).is_ok());
+        let mut buf = v2(1, 1, &[]);
+        buf[14..16].copy_from_slice(&[0xFF, 0xFF]);
+        assert!(parse(&buf, 1024).is_err());
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn read_header() {
+        let (mut client, server) = duplex(64);
+        client.write_all(b"PROXY TCP4 1.2.3.4 5.6.7.8 1234 80\r\nGET")
+            .await.unwrap();
+        client.write_all(b" /\r\n").await.unwrap();
+        drop(client);
+        let mut socket = ReadHeader {
+            socket: Some(server),
+            buf: Vec::new(),
+            max_size: 1024,
+            timer: sleep(Duration::from_secs(1)),
+        }.await.unwrap();
+        assert_eq!(socket.source(), Some("1.2.3.4:1234".parse().unwrap()));
+        assert_eq!(socket.destination(), Some("5.6.7.8:80".parse().unwrap()));
+        let mut data = Vec::new();
+        socket.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"GET /\r\n");
+    }
+}
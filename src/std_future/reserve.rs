@@ -0,0 +1,246 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+use futures03::Stream;
+use pin_project_lite::pin_project;
+
+use crate::std_future::limits::{Limited, Slots};
+
+
+/// Counters of the `Reserve` stage
+///
+/// Returned by `Reserve::stats`. It's cheap to clone and can be read
+/// from any thread while the stage is running.
+#[derive(Clone)]
+pub struct ReserveStats {
+    regular: Arc<Slots>,
+    reserved: Arc<Slots>,
+    refused: Arc<AtomicUsize>,
+}
+
+pin_project! {
+    /// A structure returned by `ListenExt::reserve`
+    ///
+    /// This stream limits connections like `listen` does, but keeps
+    /// a number of slots for connections matching a predicate (e.g.
+    /// health checks or internal clients). Accepted sockets are wrapped
+    /// into `Limited` which frees the slot when dropped.
+    ///
+    /// Matching connections occupy reserved slots first and regular
+    /// slots when reserved ones are exhausted. When all regular slots
+    /// are busy, but there are free reserved slots, the stream continues
+    /// to accept connections (as it's impossible to know the peer before
+    /// accepting), so other connections are closed immediately instead
+    /// of waiting in the kernel backlog. When all slots are busy, the
+    /// underlying stream isn't polled.
+    ///
+    /// Note: the limit of `listen` should be at least `max_connections`
+    /// plus `reserved`, otherwise connections accepted into reserved
+    /// slots wait until some regular connection is finished.
+    pub struct Reserve<S, P> {
+        #[pin]
+        stream: S,
+        predicate: P,
+        stats: ReserveStats,
+    }
+}
+
+pub fn new<S, P>(stream: S, max_connections: usize, reserved: usize,
+    predicate: P)
+    -> Reserve<S, P>
+    where S: Stream,
+          P: FnMut(&S::Item) -> bool,
+{
+    Reserve {
+        stream,
+        predicate,
+        stats: ReserveStats {
+            regular: Slots::new(max_connections),
+            reserved: Slots::new(reserved),
+            refused: Arc::new(AtomicUsize::new(0)),
+        },
+    }
+}
+
+impl ReserveStats {
+    /// Returns number of regular connections currently active
+    pub fn active(&self) -> usize {
+        self.regular.active()
+    }
+
+    /// Returns number of connections in reserved slots currently active
+    pub fn reserved(&self) -> usize {
+        self.reserved.active()
+    }
+
+    /// Total number of connections closed because only reserved slots
+    /// were free
+    pub fn refused(&self) -> usize {
+        self.refused.load(Ordering::Relaxed)
+    }
+}
+
+impl<S, P> Reserve<S, P> {
+    /// Returns a handle to connection counters
+    pub fn stats(&self) -> ReserveStats {
+        self.stats.clone()
+    }
+}
+
+impl<S, P> Stream for Reserve<S, P>
+    where S: Stream,
+          P: FnMut(&S::Item) -> bool,
+{
+    type Item = Limited<S::Item>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Option<Self::Item>>
+    {
+        let mut this = self.project();
+        let stats = &*this.stats;
+        loop {
+            let regular_free = stats.regular.poll_free(cx);
+            let reserved_free = stats.reserved.poll_free(cx);
+            if !regular_free && !reserved_free {
+                return Poll::Pending;
+            }
+            let socket = match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(socket)) => socket,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            if reserved_free && (this.predicate)(&socket) {
+                return Poll::Ready(Some(stats.reserved.take(socket)));
+            }
+            if regular_free {
+                return Poll::Ready(Some(stats.regular.take(socket)));
+            }
+            debug!("Connection limit reached, closing connection");
+            stats.refused.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::task::{Context, Poll};
+
+    use futures03::StreamExt;
+    use futures03::channel::mpsc;
+    use futures03::task::noop_waker;
+
+    use crate::std_future::ListenExt;
+
+    fn admin(name: &&str) -> bool {
+        name.starts_with("admin")
+    }
+
+    #[test]
+    fn refuse_when_only_reserved_free() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let (tx, rx) = mpsc::unbounded();
+        let mut reserve = rx.reserve(2, 1, admin);
+        let stats = reserve.stats();
+
+        tx.unbounded_send("a").unwrap();
+        tx.unbounded_send("b").unwrap();
+        tx.unbounded_send("c").unwrap();
+        let a = match reserve.poll_next_unpin(&mut cx) {
+            Poll::Ready(Some(a)) => a,
+            _ => panic!("connection is accepted"),
+        };
+        let b = match reserve.poll_next_unpin(&mut cx) {
+            Poll::Ready(Some(b)) => b,
+            _ => panic!("connection is accepted"),
+        };
+        assert_eq!((*a.get_ref(), *b.get_ref()), ("a", "b"));
+        assert_eq!(stats.active(), 2);
+        // only reserved slot is free, so regular connection is closed
+        assert!(reserve.poll_next_unpin(&mut cx).is_pending());
+        assert_eq!(stats.refused(), 1);
+
+        // trusted peer still gets in
+        tx.unbounded_send("admin").unwrap();
+        let admin = match reserve.poll_next_unpin(&mut cx) {
+            Poll::Ready(Some(admin)) => admin,
+            _ => panic!("trusted connection is accepted"),
+        };
+        assert_eq!(*admin.get_ref(), "admin");
+        assert_eq!(stats.reserved(), 1);
+
+        // all slots are busy, stream isn't polled
+        tx.unbounded_send("d").unwrap();
+        assert!(reserve.poll_next_unpin(&mut cx).is_pending());
+        assert_eq!(stats.refused(), 1);
+        drop(a);
+        let d = match reserve.poll_next_unpin(&mut cx) {
+            Poll::Ready(Some(d)) => d,
+            _ => panic!("connection is accepted to a free slot"),
+        };
+        assert_eq!(*d.get_ref(), "d");
+        assert_eq!(stats.active(), 2);
+        assert_eq!(stats.refused(), 1);
+    }
+
+    #[test]
+    fn trusted_use_regular_slots() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let (tx, rx) = mpsc::unbounded();
+        let mut reserve = rx.reserve(2, 1, admin);
+        let stats = reserve.stats();
+
+        tx.unbounded_send("admin1").unwrap();
+        tx.unbounded_send("admin2").unwrap();
+        let mut accepted = Vec::new();
+        while let Poll::Ready(Some(s)) = reserve.poll_next_unpin(&mut cx) {
+            accepted.push(s);
+        }
+        assert_eq!(accepted.len(), 2);
+        assert_eq!(stats.reserved(), 1);
+        assert_eq!(stats.active(), 1);
+    }
+
+    #[cfg(feature = "rt-tokio")]
+    #[tokio1::test(crate = "tokio1")]
+    async fn listen_limit() {
+        use std::time::Duration;
+
+        use futures03::channel::oneshot;
+        use tokio1::time::timeout;
+
+        let (tx, rx) = mpsc::unbounded::<(&str, oneshot::Receiver<()>)>();
+        let (started_tx, mut started) = mpsc::unbounded();
+        let mut finish = Vec::new();
+        let listen = rx
+            .reserve(2, 1, |&(name, _): &(&str, _)| admin(&name))
+            .map(move |mut socket| {
+                let started_tx = started_tx.clone();
+                async move {
+                    let (name, _) = *socket.get_ref();
+                    started_tx.unbounded_send(name).unwrap();
+                    // slot is held until the connection is finished
+                    (&mut socket.get_mut().1).await.ok();
+                }
+            })
+            // must be at least `max_connections` plus `reserved`
+            .listen(3);
+        tokio1::spawn(listen);
+
+        for name in &["a", "b", "c", "admin"] {
+            let (tx_done, rx_done) = oneshot::channel::<()>();
+            finish.push(tx_done);
+            tx.unbounded_send((*name, rx_done)).unwrap();
+        }
+        let mut names = Vec::new();
+        for _ in 0..3 {
+            let name = timeout(Duration::from_secs(1), started.next()).await
+                .expect("connection is started");
+            names.push(name.unwrap());
+        }
+        names.sort();
+        assert_eq!(names, vec!["a", "admin", "b"]);
+    }
+}
@@ -0,0 +1,164 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+pub use crate::peer::PeerAddr;
+
+
+/// Creates timers for `SleepOnError` and `BindMany`
+pub trait Timer {
+    /// A future that resolves when timer expires (output is ignored)
+    type Sleep: Future;
+    /// Returns a future that resolves after `duration`
+    fn sleep(&self, duration: Duration) -> Self::Sleep;
+}
+
+/// Creates listening sockets for `BindMany`
+pub trait Bind {
+    /// A listening socket
+    type Listener: Listener;
+    /// Binds a socket to the address and starts listening
+    fn bind(&self, addr: &SocketAddr) -> io::Result<Self::Listener>;
+}
+
+/// A listening socket
+pub trait Listener {
+    /// Accepted socket
+    type Socket;
+    /// Accepts a connection if there is one
+    fn poll_accept(&mut self, cx: &mut Context<'_>)
+        -> Poll<io::Result<Self::Socket>>;
+}
+
+/// A socket which knows the local address it was accepted on
+///
+/// Used to select TLS configuration for the listening address.
+pub trait LocalAddr {
+    /// Returns local address of the socket
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// Timers and sockets of tokio 1.x
+///
+/// This is a default runtime for all combinators, which requires
+/// `rt-tokio` feature (enabled by default).
+#[cfg(feature = "rt-tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tokio;
+
+/// Timers and sockets of `async-io`, which is used by smol and async-std
+///
+/// Requires `rt-async-io` feature. Accepted sockets are
+/// `async_io::Async<TcpStream>`.
+#[cfg(feature = "rt-async-io")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncIo;
+
+#[cfg(feature = "rt-tokio")]
+mod tokio_impl {
+    use std::io;
+    use std::net::{self, SocketAddr};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use tokio1::net::{TcpListener, TcpStream};
+    use tokio1::time::{sleep, Sleep};
+
+    use super::{Timer, Bind, Listener, LocalAddr, PeerAddr, Tokio};
+
+    impl Timer for Tokio {
+        type Sleep = Sleep;
+        fn sleep(&self, duration: Duration) -> Sleep {
+            sleep(duration)
+        }
+    }
+
+    impl Bind for Tokio {
+        type Listener = TcpListener;
+        fn bind(&self, addr: &SocketAddr) -> io::Result<TcpListener> {
+            let listener = net::TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        }
+    }
+
+    impl Listener for TcpListener {
+        type Socket = TcpStream;
+        fn poll_accept(&mut self, cx: &mut Context<'_>)
+            -> Poll<io::Result<TcpStream>>
+        {
+            TcpListener::poll_accept(self, cx)
+                .map(|result| result.map(|(socket, _)| socket))
+        }
+    }
+
+    impl LocalAddr for TcpStream {
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            TcpStream::local_addr(self)
+        }
+    }
+
+    impl PeerAddr for TcpStream {
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            TcpStream::peer_addr(self)
+        }
+    }
+}
+
+#[cfg(feature = "rt-async-io")]
+mod async_io_impl {
+    use std::io;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use async_io::{Async, Timer as AsyncTimer};
+    use futures03::ready;
+
+    use super::{Timer, Bind, Listener, LocalAddr, PeerAddr, AsyncIo};
+
+    impl Timer for AsyncIo {
+        type Sleep = AsyncTimer;
+        fn sleep(&self, duration: Duration) -> AsyncTimer {
+            AsyncTimer::after(duration)
+        }
+    }
+
+    impl Bind for AsyncIo {
+        type Listener = Async<TcpListener>;
+        fn bind(&self, addr: &SocketAddr) -> io::Result<Async<TcpListener>> {
+            Async::<TcpListener>::bind(*addr)
+        }
+    }
+
+    impl Listener for Async<TcpListener> {
+        type Socket = Async<TcpStream>;
+        fn poll_accept(&mut self, cx: &mut Context<'_>)
+            -> Poll<io::Result<Async<TcpStream>>>
+        {
+            loop {
+                match self.get_ref().accept() {
+                    Ok((socket, _)) => return Poll::Ready(Async::new(socket)),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        ready!(self.poll_readable(cx))?;
+                    }
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+        }
+    }
+
+    impl LocalAddr for Async<TcpStream> {
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.get_ref().local_addr()
+        }
+    }
+
+    impl PeerAddr for Async<TcpStream> {
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.get_ref().peer_addr()
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures03::{Stream, ready};
+use pin_project_lite::pin_project;
+
+use crate::std_future::runtime::Timer;
+use crate::util::connection_error;
+
+
+pin_project! {
+    /// A structure returned by `ListenExt::sleep_on_error`
+    ///
+    /// This is a stream that filters original stream for errors, ignores
+    /// some of them and sleeps on severe ones.
+    pub struct SleepOnError<S, T: Timer> {
+        #[pin]
+        stream: S,
+        delay: Duration,
+        timer: T,
+        timeout: Option<Pin<Box<T::Sleep>>>,
+    }
+}
+
+pub fn new<S, T: Timer>(stream: S, delay: Duration, timer: T)
+    -> SleepOnError<S, T>
+{
+    SleepOnError {
+        stream,
+        delay,
+        timer,
+        timeout: None,
+    }
+}
+
+impl<I, S, T> Stream for SleepOnError<S, T>
+    where S: Stream<Item=io::Result<I>>,
+          T: Timer,
+{
+    type Item = I;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Option<I>>
+    {
+        let mut this = self.project();
+        if let Some(ref mut to) = *this.timeout {
+            ready!(to.as_mut().poll(cx));
+        }
+        *this.timeout = None;
+        loop {
+            match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(Ok(item)) => return Poll::Ready(Some(item)),
+                None => return Poll::Ready(None),
+                Some(Err(ref e)) if connection_error(e) => continue,
+                Some(Err(e)) => {
+                    debug!("Accept error: {}. Sleeping {:?}...",
+                        e, this.delay);
+                    let mut delay = Box::pin(this.timer.sleep(*this.delay));
+                    match delay.as_mut().poll(cx) {
+                        Poll::Ready(_) => continue,
+                        Poll::Pending => {
+                            *this.timeout = Some(delay);
+                            return Poll::Pending;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures03::Stream;
+    use futures03::channel::oneshot;
+    use futures03::stream::iter;
+    use futures03::task::noop_waker;
+
+    use crate::std_future::ListenExt;
+    use crate::std_future::runtime::Timer;
+
+    type Sleeps = Vec<(Duration, Option<oneshot::Sender<()>>)>;
+
+    /// A timer which fires only when test says so
+    #[derive(Clone, Default)]
+    struct Manual {
+        sleeps: Arc<Mutex<Sleeps>>,
+    }
+
+    impl Manual {
+        fn durations(&self) -> Vec<Duration> {
+            self.sleeps.lock().unwrap().iter().map(|(d, _)| *d).collect()
+        }
+        fn fire(&self) {
+            for (_, tx) in self.sleeps.lock().unwrap().iter_mut() {
+                if let Some(tx) = tx.take() {
+                    tx.send(()).ok();
+                }
+            }
+        }
+    }
+
+    impl Timer for Manual {
+        type Sleep = oneshot::Receiver<()>;
+        fn sleep(&self, duration: Duration) -> oneshot::Receiver<()> {
+            let (tx, rx) = oneshot::channel();
+            self.sleeps.lock().unwrap().push((duration, Some(tx)));
+            rx
+        }
+    }
+
+    fn poll<S: Stream + Unpin>(stream: &mut S) -> Poll<Option<S::Item>> {
+        let waker = noop_waker();
+        Pin::new(stream).poll_next(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn sleep_on_error() {
+        let timer = Manual::default();
+        let items: Vec<io::Result<u32>> = vec![
+            Ok(1),
+            Err(io::ErrorKind::ConnectionReset.into()),
+            Ok(2),
+            Err(io::Error::other("too many open files")),
+            Ok(3),
+        ];
+        let delay = Duration::from_millis(100);
+        let mut stream = iter(items).sleep_on_error_with(timer.clone(), delay);
+        assert_eq!(poll(&mut stream), Poll::Ready(Some(1)));
+        // connection errors are skipped without sleeping
+        assert_eq!(poll(&mut stream), Poll::Ready(Some(2)));
+        assert_eq!(timer.durations(), vec![]);
+
+        assert_eq!(poll(&mut stream), Poll::Pending);
+        assert_eq!(timer.durations(), vec![delay]);
+        // stream isn't polled until timer fires
+        assert_eq!(poll(&mut stream), Poll::Pending);
+        assert_eq!(timer.durations(), vec![delay]);
+
+        timer.fire();
+        assert_eq!(poll(&mut stream), Poll::Ready(Some(3)));
+        assert_eq!(poll(&mut stream), Poll::Ready(None));
+    }
+}
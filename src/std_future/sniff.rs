@@ -0,0 +1,583 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures03::{Stream, StreamExt};
+use futures03::stream::FuturesUnordered;
+use pin_project_lite::pin_project;
+use tokio1::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio1::time::{sleep, Sleep};
+
+use crate::std_future::proxy::{V1_PREFIX, V2_SIGNATURE};
+use crate::peer::PeerAddr;
+use crate::std_future::runtime::LocalAddr;
+
+
+const HTTP1_METHODS: &[&[u8]] = &[
+    b"GET ", b"HEAD ", b"POST ", b"PUT ", b"DELETE ", b"CONNECT ",
+    b"OPTIONS ", b"TRACE ", b"PATCH ",
+];
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Result of a `Detector` check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+    /// Data belongs to the protocol
+    Match,
+    /// Data doesn't belong to the protocol
+    NoMatch,
+    /// More data is needed to decide
+    NeedMore,
+}
+
+/// Classifies the first bytes received on a connection
+///
+/// Implemented for `Fn(&[u8]) -> Detection` and for detectors of common
+/// protocols: `TlsClientHello`, `Http1`, `Http2Preface`, `ProxyHeader`.
+pub trait Detector {
+    /// Checks data received so far (it's never empty)
+    fn detect(&self, data: &[u8]) -> Detection;
+}
+
+/// Detects TLS handshake (a record with ClientHello message)
+#[derive(Debug, Clone, Copy)]
+pub struct TlsClientHello;
+
+/// Detects HTTP/1.x request by a method name
+#[derive(Debug, Clone, Copy)]
+pub struct Http1;
+
+/// Detects HTTP/2 connection preface (HTTP/2 with prior knowledge)
+#[derive(Debug, Clone, Copy)]
+pub struct Http2Preface;
+
+/// Detects PROXY protocol header (v1 or v2)
+#[derive(Debug, Clone, Copy)]
+pub struct ProxyHeader;
+
+type Detectors<K> = Vec<(Box<dyn Detector + Send + Sync>, K)>;
+
+pin_project! {
+    /// A structure returned by `ListenExt::sniff`
+    ///
+    /// This is a stream which reads first bytes of accepted sockets and
+    /// yields each socket along with the kind of the first matching
+    /// detector (added by `detect`, in order of priority).
+    ///
+    /// Bytes are read from the socket rather than peeked (so any
+    /// `AsyncRead` can be sniffed), and sockets are yielded wrapped into
+    /// `Sniffed`, which replays these bytes before reading further. So
+    /// handlers must read from `Sniffed`, or take the unread bytes with
+    /// `Sniffed::into_parts`, otherwise the prefix is lost.
+    ///
+    /// Socket which doesn't match any detector within `max_peek` bytes
+    /// (default is `64`) or until timeout or end of stream gets the
+    /// `fallback` kind, or is closed if there is no fallback. So for
+    /// protocols where server speaks first, fallback should be set. At
+    /// most `max_pending` (default is `100`) sockets are sniffed at once.
+    pub struct Sniff<S, K>
+        where S: Stream,
+    {
+        #[pin]
+        stream: S,
+        done: bool,
+        timeout: Duration,
+        max_peek: usize,
+        max_pending: usize,
+        detectors: Detectors<K>,
+        fallback: Option<K>,
+        pending: FuturesUnordered<Peek<S::Item>>,
+    }
+}
+
+/// A socket returned by `Sniff`
+///
+/// Returns bytes read while sniffing before reading the underlying socket.
+#[derive(Debug)]
+pub struct Sniffed<T> {
+    socket: T,
+    prefix: Vec<u8>,
+    pos: usize,
+}
+
+#[derive(PartialEq)]
+enum State {
+    Data,
+    Full,
+    Eof,
+    TimedOut,
+}
+
+struct Peek<T> {
+    peeked: Option<Peeked<T>>,
+    max_peek: usize,
+}
+
+struct Peeked<T> {
+    socket: T,
+    buf: Vec<u8>,
+    timer: Pin<Box<Sleep>>,
+    state: State,
+}
+
+enum Classified<K> {
+    Matched(K),
+    NoMatch,
+    NeedMore,
+}
+
+fn prefix(data: &[u8], pattern: &[u8]) -> Detection {
+    if data.len() >= pattern.len() {
+        if data.starts_with(pattern) {
+            Detection::Match
+        } else {
+            Detection::NoMatch
+        }
+    } else if pattern.starts_with(data) {
+        Detection::NeedMore
+    } else {
+        Detection::NoMatch
+    }
+}
+
+impl<F: Fn(&[u8]) -> Detection> Detector for F {
+    fn detect(&self, data: &[u8]) -> Detection {
+        (self)(data)
+    }
+}
+
+impl Detector for TlsClientHello {
+    fn detect(&self, data: &[u8]) -> Detection {
+        // content type `handshake`, major version 3, message `ClientHello`
+        match data {
+            [0x16] | [0x16, 0x03] | [0x16, 0x03, _] |
+            [0x16, 0x03, _, _] | [0x16, 0x03, _, _, _]
+            => Detection::NeedMore,
+            [0x16, 0x03, _, _, _, 0x01, ..] => Detection::Match,
+            _ => Detection::NoMatch,
+        }
+    }
+}
+
+impl Detector for Http1 {
+    fn detect(&self, data: &[u8]) -> Detection {
+        let mut result = Detection::NoMatch;
+        for method in HTTP1_METHODS {
+            match prefix(data, method) {
+                Detection::Match => return Detection::Match,
+                Detection::NeedMore => result = Detection::NeedMore,
+                Detection::NoMatch => {}
+            }
+        }
+        result
+    }
+}
+
+impl Detector for Http2Preface {
+    fn detect(&self, data: &[u8]) -> Detection {
+        prefix(data, HTTP2_PREFACE)
+    }
+}
+
+impl Detector for ProxyHeader {
+    fn detect(&self, data: &[u8]) -> Detection {
+        match prefix(data, V1_PREFIX) {
+            Detection::NoMatch => prefix(data, V2_SIGNATURE),
+            result => result,
+        }
+    }
+}
+
+pub fn new<S, K>(stream: S, timeout: Duration) -> Sniff<S, K>
+    where S: Stream,
+          S::Item: AsyncRead + Unpin,
+{
+    Sniff {
+        stream,
+        done: false,
+        timeout,
+        max_peek: 64,
+        max_pending: 100,
+        detectors: Vec::new(),
+        fallback: None,
+        pending: FuturesUnordered::new(),
+    }
+}
+
+impl<S: Stream, K> Sniff<S, K> {
+    /// Adds a detector, sockets matching it are yielded with `kind`
+    ///
+    /// Detectors are checked in the order they are added.
+    pub fn detect<D>(&mut self, detector: D, kind: K) -> &mut Self
+        where D: Detector + Send + Sync + 'static,
+    {
+        self.detectors.push((Box::new(detector), kind));
+        self
+    }
+
+    /// Sets a kind for sockets which don't match any detector
+    pub fn fallback(&mut self, kind: K) -> &mut Self {
+        self.fallback = Some(kind);
+        self
+    }
+
+    /// Sets maximum number of bytes read to detect protocol
+    /// (default is `64`)
+    ///
+    /// Values less than `1` are treated as `1`, as no protocol can be
+    /// detected without data.
+    pub fn max_peek(&mut self, max_peek: usize) -> &mut Self {
+        self.max_peek = max_peek.max(1);
+        self
+    }
+
+    /// Sets maximum number of sockets sniffed at once (default is `100`)
+    pub fn max_pending(&mut self, max_pending: usize) -> &mut Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Returns number of sockets currently being sniffed
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl<T> Sniffed<T> {
+    /// Returns bytes received while sniffing the protocol
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Returns a reference to the underlying socket
+    pub fn get_ref(&self) -> &T {
+        &self.socket
+    }
+
+    /// Returns the underlying socket and bytes of the prefix which
+    /// haven't been read yet
+    pub fn into_parts(mut self) -> (T, Vec<u8>) {
+        self.prefix.drain(..self.pos);
+        (self.socket, self.prefix)
+    }
+}
+
+impl<T: PeerAddr> PeerAddr for Sniffed<T> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+}
+
+impl<T: LocalAddr> LocalAddr for Sniffed<T> {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Sniffed<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>)
+        -> Poll<io::Result<()>>
+    {
+        let this = self.get_mut();
+        if this.pos < this.prefix.len() {
+            let n = buf.remaining().min(this.prefix.len() - this.pos);
+            buf.put_slice(&this.prefix[this.pos..this.pos + n]);
+            this.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.socket).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Sniffed<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+        -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_flush(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.get_mut().socket).poll_shutdown(cx)
+    }
+}
+
+fn classify<K: Clone>(detectors: &Detectors<K>, data: &[u8], last: bool)
+    -> Classified<K>
+{
+    for (detector, kind) in detectors {
+        match detector.detect(data) {
+            Detection::Match => return Classified::Matched(kind.clone()),
+            // when no more data is expected, skip to next detector
+            Detection::NeedMore if !last => return Classified::NeedMore,
+            Detection::NeedMore | Detection::NoMatch => {}
+        }
+    }
+    Classified::NoMatch
+}
+
+impl<T: AsyncRead + Unpin> Future for Peek<T> {
+    type Output = io::Result<Peeked<T>>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Self::Output>
+    {
+        let max_peek = self.max_peek;
+        let peeked = self.peeked.as_mut()
+            .expect("future is not polled after completion");
+        let len = peeked.buf.len();
+        peeked.buf.resize(max_peek, 0);
+        let mut read_buf = ReadBuf::new(&mut peeked.buf[len..]);
+        let result = Pin::new(&mut peeked.socket)
+            .poll_read(cx, &mut read_buf);
+        let filled = read_buf.filled().len();
+        peeked.buf.truncate(len + filled);
+        peeked.state = match result {
+            Poll::Ready(Ok(())) if filled == 0 => State::Eof,
+            Poll::Ready(Ok(())) if peeked.buf.len() >= max_peek => {
+                State::Full
+            }
+            Poll::Ready(Ok(())) => State::Data,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => match peeked.timer.as_mut().poll(cx) {
+                Poll::Ready(()) => State::TimedOut,
+                Poll::Pending => return Poll::Pending,
+            },
+        };
+        Poll::Ready(Ok(self.peeked.take()
+            .expect("future is not polled after completion")))
+    }
+}
+
+impl<S: Stream, K: Clone> Stream for Sniff<S, K>
+    where S::Item: AsyncRead + Unpin,
+{
+    type Item = (K, Sniffed<S::Item>);
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>)
+        -> Poll<Option<Self::Item>>
+    {
+        let mut this = self.project();
+        loop {
+            while !*this.done && this.pending.len() < *this.max_pending {
+                match this.stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(socket)) => {
+                        this.pending.push(Peek {
+                            peeked: Some(Peeked {
+                                socket,
+                                buf: Vec::new(),
+                                timer: Box::pin(sleep(*this.timeout)),
+                                state: State::Data,
+                            }),
+                            max_peek: *this.max_peek,
+                        });
+                    }
+                    Poll::Ready(None) => *this.done = true,
+                    Poll::Pending => break,
+                }
+            }
+            let peeked = match this.pending.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(peeked))) => peeked,
+                Poll::Ready(Some(Err(e))) => {
+                    debug!("Error reading protocol prefix: {}", e);
+                    continue;
+                }
+                Poll::Ready(None) if *this.done => return Poll::Ready(None),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            };
+            if peeked.buf.is_empty() && peeked.state == State::Eof {
+                debug!("Connection closed before sending any data");
+                continue;
+            }
+            let last = peeked.state != State::Data;
+            let kind = if peeked.buf.is_empty() {
+                // timed out, e.g. server speaks first
+                this.fallback.clone()
+            } else {
+                match classify(this.detectors, &peeked.buf, last) {
+                    Classified::Matched(kind) => Some(kind),
+                    Classified::NoMatch => this.fallback.clone(),
+                    Classified::NeedMore => {
+                        this.pending.push(Peek {
+                            peeked: Some(peeked),
+                            max_peek: *this.max_peek,
+                        });
+                        continue;
+                    }
+                }
+            };
+            match kind {
+                Some(kind) => {
+                    return Poll::Ready(Some((kind, Sniffed {
+                        socket: peeked.socket,
+                        prefix: peeked.buf,
+                        pos: 0,
+                    })));
+                }
+                None => debug!("Connection of unknown protocol is closed"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures03::StreamExt;
+    use futures03::stream::iter;
+    use tokio1::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::{Detector, Detection, TlsClientHello, Http1, Http2Preface};
+    use super::{ProxyHeader, HTTP2_PREFACE};
+    use crate::std_future::ListenExt;
+    use crate::std_future::proxy::V2_SIGNATURE;
+
+    #[test]
+    fn tls_client_hello() {
+        let hello = [0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00];
+        assert_eq!(TlsClientHello.detect(&hello), Detection::Match);
+        assert_eq!(TlsClientHello.detect(&hello[..2]), Detection::NeedMore);
+        assert_eq!(TlsClientHello.detect(&hello[..5]), Detection::NeedMore);
+        // server hello
+        let server = [0x16, 0x03, 0x03, 0x00, 0x10, 0x02];
+        assert_eq!(TlsClientHello.detect(&server), Detection::NoMatch);
+        assert_eq!(TlsClientHello.detect(&[0x16, 0x02]), Detection::NoMatch);
+        assert_eq!(TlsClientHello.detect(b"GET / "), Detection::NoMatch);
+    }
+
+    #[test]
+    fn http1() {
+        assert_eq!(Http1.detect(b"GET / HTTP/1.1"), Detection::Match);
+        assert_eq!(Http1.detect(b"OPTIONS * "), Detection::Match);
+        assert_eq!(Http1.detect(b"GE"), Detection::NeedMore);
+        // both `POST` and `PUT` are possible
+        assert_eq!(Http1.detect(b"P"), Detection::NeedMore);
+        assert_eq!(Http1.detect(b"GETX"), Detection::NoMatch);
+        assert_eq!(Http1.detect(b"get / "), Detection::NoMatch);
+        assert_eq!(Http1.detect(HTTP2_PREFACE), Detection::NoMatch);
+    }
+
+    #[test]
+    fn http2_preface() {
+        assert_eq!(Http2Preface.detect(HTTP2_PREFACE), Detection::Match);
+        assert_eq!(Http2Preface.detect(b"PRI * "), Detection::NeedMore);
+        assert_eq!(Http2Preface.detect(b"PRI / "), Detection::NoMatch);
+        assert_eq!(Http2Preface.detect(b"GET / "), Detection::NoMatch);
+    }
+
+    #[test]
+    fn proxy_header() {
+        let v1 = b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 443\r\n";
+        assert_eq!(ProxyHeader.detect(v1), Detection::Match);
+        assert_eq!(ProxyHeader.detect(b"PROX"), Detection::NeedMore);
+        assert_eq!(ProxyHeader.detect(V2_SIGNATURE), Detection::Match);
+        assert_eq!(ProxyHeader.detect(&V2_SIGNATURE[..3]),
+                   Detection::NeedMore);
+        assert_eq!(ProxyHeader.detect(b"\r\n\r\nX"), Detection::NoMatch);
+        assert_eq!(ProxyHeader.detect(b"GET / "), Detection::NoMatch);
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn kinds() {
+        let (mut tls, tls_server) = duplex(1024);
+        let (mut http, http_server) = duplex(1024);
+        let (mut unknown, unknown_server) = duplex(1024);
+        let (_silent, silent_server) = duplex(1024);
+        let (closed, closed_server) = duplex(1024);
+        tls.write_all(&[0x16, 0x03, 0x01, 0x02, 0x00, 0x01]).await.unwrap();
+        http.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        unknown.write_all(b"SSH-2.0-OpenSSH\r\n").await.unwrap();
+        drop(closed);
+
+        let sockets = vec![tls_server, http_server, unknown_server,
+                           silent_server, closed_server];
+        let mut sniff = iter(sockets).sniff(Duration::from_millis(50));
+        sniff.detect(TlsClientHello, "tls")
+             .detect(Http1, "http")
+             .fallback("other");
+        let mut kinds = sniff.map(|(kind, _)| kind).collect::<Vec<_>>().await;
+        kinds.sort();
+        // connection closed without data is closed even with fallback
+        assert_eq!(kinds, vec!["http", "other", "other", "tls"]);
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn no_fallback() {
+        let (mut http, http_server) = duplex(1024);
+        let (mut unknown, unknown_server) = duplex(1024);
+        let (_silent, silent_server) = duplex(1024);
+        http.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        unknown.write_all(b"SSH-2.0-OpenSSH\r\n").await.unwrap();
+
+        let sockets = vec![http_server, unknown_server, silent_server];
+        let mut sniff = iter(sockets).sniff(Duration::from_millis(50));
+        sniff.detect(Http1, "http");
+        let kinds = sniff.map(|(kind, _)| kind).collect::<Vec<_>>().await;
+        assert_eq!(kinds, vec!["http"]);
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn replay() {
+        let (mut client, server) = duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        drop(client);
+        let mut sniff = iter(vec![server]).sniff(Duration::from_secs(5));
+        sniff.detect(Http1, ()).max_peek(6);
+        let ((), mut socket) = sniff.next().await.unwrap();
+        assert_eq!(socket.prefix(), b"GET / ");
+
+        // prefix is replayed even if it doesn't fit the buffer
+        let mut start = [0; 2];
+        socket.read_exact(&mut start).await.unwrap();
+        assert_eq!(&start, b"GE");
+        let mut rest = Vec::new();
+        socket.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"T / HTTP/1.1\r\n\r\n");
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn into_parts() {
+        let (mut client, server) = duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        drop(client);
+        let mut sniff = iter(vec![server]).sniff(Duration::from_secs(5));
+        sniff.detect(Http1, ()).max_peek(6);
+        let ((), mut socket) = sniff.next().await.unwrap();
+        let mut start = [0; 2];
+        socket.read_exact(&mut start).await.unwrap();
+        let (mut socket, prefix) = socket.into_parts();
+        assert_eq!(prefix, b"T / ");
+        let mut rest = Vec::new();
+        socket.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"HTTP/1.1\r\n\r\n");
+    }
+
+    #[tokio1::test(crate = "tokio1")]
+    async fn zero_max_peek() {
+        let (mut client, server) = duplex(1024);
+        client.write_all(b"X-protocol\r\n").await.unwrap();
+        let mut sniff = iter(vec![server]).sniff(Duration::from_secs(5));
+        let detector = |data: &[u8]| if data[0] == b'X' {
+            Detection::Match
+        } else {
+            Detection::NoMatch
+        };
+        // no bytes to detect protocol is treated as a single byte
+        sniff.detect(detector, "x").max_peek(0);
+        let (kind, mut socket) = sniff.next().await.unwrap();
+        assert_eq!(kind, "x");
+        assert_eq!(socket.prefix(), b"X");
+        drop(client);
+        let mut data = Vec::new();
+        socket.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"X-protocol\r\n");
+    }
+}
@@ -0,0 +1,201 @@
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use futures03::Stream;
+#[cfg(feature = "rt-tokio")]
+use tokio1::io::AsyncRead;
+#[cfg(feature = "rustls")]
+use tokio1::io::AsyncWrite;
+
+use crate::std_future::ban::{self, BanTable};
+use crate::std_future::filter::{self, AccessList};
+use crate::std_future::listen;
+#[cfg(feature = "rt-tokio")]
+use crate::std_future::proxy;
+use crate::std_future::reserve;
+use crate::std_future::runtime::Timer;
+#[cfg(feature = "rustls")]
+use crate::std_future::runtime::LocalAddr;
+use crate::peer::PeerAddr;
+#[cfg(feature = "rt-tokio")]
+use crate::std_future::runtime::Tokio;
+use crate::std_future::sleep_on_error;
+#[cfg(feature = "rt-tokio")]
+use crate::std_future::sniff;
+#[cfg(feature = "rustls")]
+use crate::std_future::tls;
+
+
+/// An extension trait that provides necessary combinators for turning
+/// a stream of `accept()` events into a full-featured connection listener
+///
+/// This is a counterpart of the `ListenExt` from the crate root for
+/// `std::future`. It's implemented for every `futures::Stream`.
+pub trait ListenExt: Stream {
+    /// Turns a listening stream (e.g. `BindMany`) into a stream that
+    /// supresses errors and sleeps on resource shortage, effectively
+    /// allowing listening stream to resume on error.
+    ///
+    /// Uses tokio timers, see `sleep_on_error_with` for other runtimes.
+    #[cfg(feature = "rt-tokio")]
+    fn sleep_on_error<I>(self, delay: Duration)
+        -> sleep_on_error::SleepOnError<Self, Tokio>
+        where Self: Stream<Item=io::Result<I>> + Sized,
+    {
+        sleep_on_error::new(self, delay, Tokio)
+    }
+    /// Same as `sleep_on_error` but uses timers of the specified runtime
+    /// (e.g. `AsyncIo`)
+    fn sleep_on_error_with<I, T>(self, timer: T, delay: Duration)
+        -> sleep_on_error::SleepOnError<Self, T>
+        where Self: Stream<Item=io::Result<I>> + Sized,
+              T: Timer,
+    {
+        sleep_on_error::new(self, delay, timer)
+    }
+    /// Turns a stream of protocol handlers usually produced by mapping
+    /// a stream of accepted connections into a future which runs at most
+    /// `max_connections` handlers at once
+    fn listen(self, max_connections: usize) -> listen::Listen<Self>
+        where Self: Sized,
+              Self::Item: Future<Output=()>,
+    {
+        listen::new(self, max_connections)
+    }
+    /// Limits connections to `max_connections` but keeps `reserved` slots
+    /// for sockets matching `predicate`
+    ///
+    /// Predicate may check peer address (e.g. using `Cidr`) or the local
+    /// address to find out which of `BindMany` addresses socket was
+    /// accepted on:
+    ///
+    /// ```rust,ignore
+    /// let internal: Cidr = "10.0.0.0/8".parse()?;
+    /// BindMany::new(address_stream)
+    ///     .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+    ///     .reserve(10000, 50, move |socket: &TcpStream| {
+    ///         socket.peer_addr()
+    ///             .map(|a| internal.contains(&a.ip())).unwrap_or(false)
+    ///     })
+    ///     .map(|socket| handle(socket))
+    ///     .listen(10050)
+    ///     .await;
+    /// ```
+    ///
+    /// See `Reserve` for details.
+    fn reserve<P>(self, max_connections: usize, reserved: usize,
+        predicate: P)
+        -> reserve::Reserve<Self, P>
+        where Self: Sized,
+              P: FnMut(&Self::Item) -> bool,
+    {
+        reserve::new(self, max_connections, reserved, predicate)
+    }
+    /// Closes sockets from peers rejected by an access list
+    ///
+    /// Receives a stream of `AccessList` in the same manner as `BindMany`
+    /// receives a stream of addresses, so lists can be updated at runtime.
+    /// Sockets are checked before any handler is run. When used after
+    /// `proxy_protocol` the address of the original client is checked.
+    fn filter_peers<L>(self, lists: L) -> filter::PeerFilter<Self, L>
+        where Self: Sized,
+              Self::Item: PeerAddr,
+              L: Stream<Item=AccessList>,
+    {
+        filter::new(self, lists)
+    }
+    /// Closes sockets from peers banned in the `BanTable`
+    ///
+    /// Every connection is counted against connection rate of the table,
+    /// so peers opening connections too fast are banned here. When used
+    /// after `proxy_protocol` the original client is banned, not a proxy.
+    fn ban_peers(self, table: &BanTable) -> ban::BanPeers<Self>
+        where Self: Sized,
+              Self::Item: PeerAddr,
+    {
+        ban::new(self, table)
+    }
+    /// Reads PROXY protocol (v1 or v2) header on accepted sockets
+    ///
+    /// Should be used right after `sleep_on_error` when listening behind
+    /// a load balancer (like HAProxy or AWS NLB). Yields `Proxied` sockets
+    /// which report the real client address. Connections which haven't
+    /// sent a valid header within `timeout` are closed. See
+    /// `ProxyProtocol` for limits and trusted proxies.
+    #[cfg(feature = "rt-tokio")]
+    fn proxy_protocol(self, timeout: Duration)
+        -> proxy::ProxyProtocol<Self>
+        where Self: Sized,
+              Self::Item: AsyncRead + PeerAddr + Unpin,
+    {
+        proxy::new(self, timeout)
+    }
+    /// Detects protocol by the first bytes received on accepted sockets
+    ///
+    /// Yields pairs of a kind and a `Sniffed` socket, so a handler can be
+    /// chosen by matching on the kind:
+    ///
+    /// ```rust,ignore
+    /// let mut sniff = BindMany::new(address_stream)
+    ///     .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+    ///     .sniff(Duration::from_secs(5));
+    /// sniff.detect(TlsClientHello, Proto::Https)
+    ///      .detect(Http1, Proto::Http);
+    /// sniff
+    ///     .map(|(kind, socket)| match kind {
+    ///         Proto::Https => serve_https(socket).boxed(),
+    ///         Proto::Http => serve_http(socket).boxed(),
+    ///     })
+    ///     .listen(MAX_SIMULTANEOUS_CONNECTIONS)
+    ///     .await;
+    /// ```
+    ///
+    /// Sniffed bytes are consumed from the socket, `Sniffed` replays them
+    /// to the handler. Sockets which haven't sent enough data to detect
+    /// protocol within `timeout` get a fallback kind. See `Sniff` for
+    /// details.
+    #[cfg(feature = "rt-tokio")]
+    fn sniff<K>(self, timeout: Duration) -> sniff::Sniff<Self, K>
+        where Self: Sized,
+              Self::Item: AsyncRead + Unpin,
+    {
+        sniff::new(self, timeout)
+    }
+    /// Performs TLS handshake on accepted sockets
+    ///
+    /// Should be used between `sleep_on_error` and `map`, so protocol
+    /// handlers receive established TLS streams. Handshakes that haven't
+    /// finished within `timeout` are dropped. See `TlsHandshake` for
+    /// the limit of concurrent handshakes.
+    ///
+    /// `config` is either a `ServerConfig` or a `TlsConfig` with
+    /// configurations for specific listening addresses.
+    #[cfg(feature = "rustls")]
+    fn tls_handshake<T>(self, config: T, timeout: Duration)
+        -> tls::TlsHandshake<Self>
+        where Self: Sized,
+              Self::Item: AsyncRead + AsyncWrite + LocalAddr + Unpin,
+              T: Into<tls::TlsConfig>,
+    {
+        tls::new(self, config.into(), timeout)
+    }
+    /// Same as `tls_handshake` but receives TLS configuration from
+    /// a stream, so certificates can be reloaded
+    ///
+    /// New configuration applies to new handshakes, established
+    /// connections are not interrupted. Like the stream of addresses of
+    /// `BindMany`, the stream of configs should never end, otherwise the
+    /// last configuration is used forever.
+    #[cfg(feature = "rustls")]
+    fn tls_handshake_reload<C>(self, configs: C, timeout: Duration)
+        -> tls::TlsHandshake<Self, C>
+        where Self: Sized,
+              Self::Item: AsyncRead + AsyncWrite + LocalAddr + Unpin,
+              C: Stream<Item=tls::TlsConfig>,
+    {
+        tls::with_configs(self, configs, timeout)
+    }
+}
+
+impl<T: Stream> ListenExt for T {}
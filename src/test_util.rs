@@ -0,0 +1,94 @@
+//! Helpers for unit tests of the futures 0.1 combinators
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Future, Stream, Poll};
+use futures::executor::{self, Notify, NotifyHandle, Spawn};
+use futures::sync::oneshot;
+
+use crate::traits::PeerAddr;
+
+
+/// A connection future used in tests
+pub type Conn = Box<dyn Future<Item=(), Error=()> + Send>;
+
+struct Counter(AtomicUsize);
+
+/// A future or a stream polled manually, which counts wakeups
+pub struct Task<T> {
+    spawn: Spawn<T>,
+    counter: Arc<Counter>,
+}
+
+impl Notify for Counter {
+    fn notify(&self, _id: usize) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl<T> Task<T> {
+    pub fn new(value: T) -> Task<T> {
+        Task {
+            spawn: executor::spawn(value),
+            counter: Arc::new(Counter(AtomicUsize::new(0))),
+        }
+    }
+
+    /// Returns `true` if task was woken up since the last call
+    pub fn woken(&self) -> bool {
+        self.counter.0.swap(0, Ordering::SeqCst) > 0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.spawn.get_mut()
+    }
+
+    fn handle(&self) -> NotifyHandle {
+        NotifyHandle::from(self.counter.clone())
+    }
+}
+
+impl<F: Future> Task<F> {
+    pub fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let handle = self.handle();
+        self.spawn.poll_future_notify(&handle, 0)
+    }
+}
+
+impl<S: Stream> Task<S> {
+    pub fn poll_next(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        let handle = self.handle();
+        self.spawn.poll_stream_notify(&handle, 0)
+    }
+}
+
+/// An accepted socket with the specified peer address
+#[derive(Debug)]
+pub struct Socket(pub SocketAddr);
+
+impl PeerAddr for Socket {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.0)
+    }
+}
+
+/// Returns a socket with the peer address parsed from string
+pub fn socket(addr: &str) -> Socket {
+    Socket(addr.parse().expect("valid address"))
+}
+
+/// Returns a connection future which finishes successfully when sender
+/// is used and with an error when sender is dropped
+pub fn conn() -> (oneshot::Sender<()>, Conn) {
+    let (tx, rx) = oneshot::channel();
+    (tx, Box::new(rx.map_err(|_| ())))
+}
+
+/// Returns a connection future which panics when polled
+pub fn panicking() -> Conn {
+    Box::new(futures::future::lazy(|| -> Result<(), ()> {
+        panic!("test panic")
+    }))
+}
@@ -1,9 +1,21 @@
+use std::io;
+use std::net::SocketAddr;
 use std::time::Duration;
 
-use futures::{Stream, IntoFuture};
+use futures::{Stream, Poll};
+use futures::sync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
+use tokio::net::TcpStream;
 
-use sleep_on_error;
-use listen;
+use crate::connection::Connection;
+use crate::sleep_on_error;
+use crate::listen;
+use crate::shutdown::{self, Shutdown};
+use crate::per_ip;
+use crate::rate_limit;
+use crate::first_byte;
+use crate::overload;
+
+pub use crate::peer::PeerAddr;
 
 
 /// An extension trait that provides necessary combinators for turning
@@ -24,13 +36,165 @@ pub trait ListenExt: Stream {
         sleep_on_error::new(self, delay)
     }
     /// Turns a stream of protocol handlers usually produced by mapping
-    /// a stream of accepted cnnec
+    /// a stream of accepted connections into a future which runs at most
+    /// `max_connections` handlers at once
+    ///
+    /// Stream items are connection futures or futures wrapped into `Peer`,
+    /// see `Listen` for more options.
     fn listen(self, max_connections: usize) -> listen::Listen<Self>
         where Self: Sized,
-              Self::Item: IntoFuture<Item=(), Error=()>,
+              Self::Item: Connection<Error=()>,
     {
         listen::new(self, max_connections)
     }
+    /// Same as `listen` but connection futures can have any error type
+    ///
+    /// Every error is passed to the `callback` along with the peer address
+    /// (which is known if connection future is wrapped into `Peer`):
+    ///
+    /// ```rust,ignore
+    ///   listener.incoming()
+    ///   .sleep_on_error(TIME_TO_WAIT_ON_ERROR)
+    ///   .map(|socket| Proto::new(socket))
+    ///   .listen_with_errors(MAX_SIMULTANEOUS_CONNECTIONS, |peer, e| {
+    ///       error!("Connection error {:?}: {}", peer, e);
+    ///   })
+    /// ```
+    fn listen_with_errors<F>(self, max_connections: usize, callback: F)
+        -> listen::Listen<Self, F>
+        where Self: Sized,
+              Self::Item: Connection,
+              F: FnMut(Option<SocketAddr>,
+                       <Self::Item as Connection>::Error),
+    {
+        listen::with_errors(self, max_connections, callback)
+    }
+    /// Same as `listen_with_errors` but errors are sent to a stream
+    ///
+    /// This is useful to process errors in a separate task. Errors are
+    /// buffered without a limit, so stream should be processed
+    /// continuously. If the stream is dropped errors are ignored.
+    fn listen_with_error_stream(self, max_connections: usize)
+        -> (listen::Listen<Self, ErrorSender<Self>>, ErrorStream<Self>)
+        where Self: Sized,
+              Self::Item: Connection,
+    {
+        let (tx, rx) = unbounded();
+        (listen::with_errors(self, max_connections, tx), rx)
+    }
+    /// Attaches a shutdown `Token` to every item of the stream
+    ///
+    /// Returns a stream of `(item, token)` pairs, where the token should be
+    /// passed to the protocol handler, so it can finish current request
+    /// and close the connection cleanly when `Shutdown::shutdown` is
+    /// called. After shutdown is requested stream is terminated, so no
+    /// new connections are accepted.
+    fn with_shutdown(self, shutdown: &Shutdown)
+        -> shutdown::WithShutdown<Self>
+        where Self: Sized,
+    {
+        shutdown::new(self, shutdown)
+    }
+    /// Limits number of simultaneous connections from a single client IP
+    ///
+    /// Connections over the limit are closed immediately by default, see
+    /// `PerIpLimit` for other options. Works on a stream of accepted
+    /// sockets, so should be used before mapping them into protocol
+    /// handlers.
+    fn per_ip_limit(self, max_per_ip: usize) -> per_ip::PerIpLimit<Self>
+        where Self: Sized,
+              Self::Item: PeerAddr,
+    {
+        per_ip::new(self, max_per_ip)
+    }
+    /// Limits the rate of accepted connections
+    ///
+    /// Uses a token bucket which holds up to `burst` tokens and is refilled
+    /// at `per_second` rate. When there are no tokens, the underlying
+    /// stream isn't polled so clients wait in the kernel backlog rather
+    /// than being accepted and closed.
+    ///
+    /// Also see `BindMany::socket_rate_limit` for the limit per listening socket.
+    fn rate_limit(self, per_second: u32, burst: u32)
+        -> rate_limit::RateLimit<Self>
+        where Self: Sized,
+    {
+        rate_limit::new(self, per_second, burst)
+    }
+    /// Limits the rate of connections from a single client IP address
+    ///
+    /// Like `rate_limit` but with a token bucket per client. Connections
+    /// over the limit are closed immediately, because client address
+    /// is not known until connection is accepted.
+    fn peer_rate_limit(self, per_second: u32, burst: u32)
+        -> rate_limit::PeerRateLimit<Self>
+        where Self: Sized,
+              Self::Item: PeerAddr,
+    {
+        rate_limit::new_per_peer(self, per_second, burst)
+    }
+    /// Holds accepted sockets until the client sends something
+    ///
+    /// Sockets that didn't send any data within `timeout` are closed
+    /// before they are mapped into protocol handlers, so they don't take
+    /// slots of the `listen` (this is a protection against slowloris-style
+    /// attacks). See `FirstByteTimeout` for more info.
+    fn first_byte_timeout(self, timeout: Duration)
+        -> first_byte::FirstByteTimeout<Self>
+        where Self: Sized,
+              Self::Item: Peek,
+    {
+        first_byte::new(self, timeout)
+    }
+    /// Keeps accepting connections over the limit to reject them
+    ///
+    /// When there are `max_connections` active connections, new sockets
+    /// are passed to the `reject` policy (`Close`, `Response` or
+    /// a function returning a future) instead of leaving clients in the
+    /// kernel backlog. Should be used before `map`, and `listen` must
+    /// have a larger limit, otherwise it stops polling the stream before
+    /// anything is rejected. See `Overload` for more info.
+    fn overload<R>(self, max_connections: usize, reject: R)
+        -> overload::Overload<Self, R>
+        where Self: Sized,
+              R: overload::Reject<Self::Item>,
+    {
+        overload::new(self, max_connections, reject)
+    }
 }
 
 impl<T: Stream> ListenExt for T {}
+
+/// Sending half of the error stream returned by
+/// `ListenExt::listen_with_error_stream`
+pub type ErrorSender<S> = UnboundedSender<(Option<SocketAddr>,
+    <<S as Stream>::Item as Connection>::Error)>;
+
+/// Error stream returned by `ListenExt::listen_with_error_stream`
+pub type ErrorStream<S> = UnboundedReceiver<(Option<SocketAddr>,
+    <<S as Stream>::Item as Connection>::Error)>;
+
+/// A socket which allows to look at incoming data without consuming it
+///
+/// This is implemented for `TcpStream` and for wrappers returned by
+/// combinators of this crate.
+pub trait Peek {
+    /// Receives data from the socket without removing it from the queue
+    ///
+    /// On success returns the number of bytes peeked, `0` means
+    /// end-of-stream. If no data is available, current task is notified
+    /// when it arrives.
+    fn poll_peek(&mut self, buf: &mut [u8]) -> Poll<usize, io::Error>;
+}
+
+impl PeerAddr for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
+impl Peek for TcpStream {
+    fn poll_peek(&mut self, buf: &mut [u8]) -> Poll<usize, io::Error> {
+        TcpStream::poll_peek(self, buf)
+    }
+}
@@ -0,0 +1,50 @@
+//! Helpers shared by combinators of the crate root and `std_future`
+use std::any::Any;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+
+
+/// This function defines errors that are per-connection. Which basically
+/// means that if we get this error from `accept()` system call it means
+/// next connection might be ready to be accepted.
+///
+/// All other errors will incur a timeout before next `accept()` is performed.
+/// The timeout is useful to handle resource exhaustion errors like ENFILE
+/// and EMFILE. Otherwise, could enter into tight loop.
+pub fn connection_error(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::ConnectionRefused ||
+    e.kind() == io::ErrorKind::ConnectionAborted ||
+    e.kind() == io::ErrorKind::ConnectionReset
+}
+
+/// Returns the message of `panic!()` if possible
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "<unknown>"
+    }
+}
+
+/// Masks IPv6 address by the prefix, so the whole network is one client
+///
+/// IPv4 and IPv4-mapped IPv6 addresses are returned as IPv4 ones.
+pub fn mask(ip: IpAddr, ipv6_prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return IpAddr::V4(ip);
+            }
+            let bits = u128::from(ip);
+            let mask = match ipv6_prefix {
+                0 => 0,
+                n if n >= 128 => !0,
+                n => !0u128 << (128 - n),
+            };
+            IpAddr::V6(Ipv6Addr::from(bits & mask))
+        }
+    }
+}